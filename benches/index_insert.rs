@@ -0,0 +1,95 @@
+//! Inserts offers in shuffled start-date order into a single region/duration
+//! bucket and reports the insert rate, compared with the sorted `Vec::insert`
+//! the index used before.
+//!
+//! Run with `cargo bench --bench index_insert`. `BENCH_OFFERS` overrides the
+//! number of offers (default 10M).
+
+use clueless::currency::Currency;
+use clueless::db_models::Offer;
+use clueless::index_tree::{IndexTree, ROOT_REGION};
+use std::hint::black_box;
+use std::time::Instant;
+
+const DAY_MS: u64 = 1000 * 60 * 60 * 24;
+
+/// The `Vec::insert` baseline is quadratic, so it only gets a small prefix.
+const VEC_BASELINE_OFFERS: usize = 200_000;
+
+fn shuffled_start_dates(count: usize) -> Vec<u64> {
+    let mut dates: Vec<u64> = (0..count as u64).map(|i| i * 60_000).collect();
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    for i in (1..dates.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        dates.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+    dates
+}
+
+fn offer(idx: u32, start_date: u64) -> Offer {
+    Offer {
+        idx,
+        id: String::new(),
+        data: String::new(),
+        most_specific_region_id: 0,
+        start_date,
+        end_date: start_date + 3 * DAY_MS,
+        number_seats: 5,
+        price: 0,
+        currency: Currency::EUR,
+        car_type: "small".parse().unwrap(),
+        has_vollkasko: false,
+        free_kilometers: 0,
+        expires_at: u64::MAX,
+        flexible: false,
+        attributes: Box::default(),
+    }
+}
+
+fn main() {
+    let count = std::env::var("BENCH_OFFERS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(10_000_000);
+    let dates = shuffled_start_dates(count);
+
+    let mut tree = IndexTree::populate_with_regions(&ROOT_REGION);
+    let started = Instant::now();
+    for (idx, &start_date) in dates.iter().enumerate() {
+        tree.insert_offer(0, &offer(idx as u32, start_date));
+    }
+    let elapsed = started.elapsed();
+    println!(
+        "IndexTree::insert_offer: {} shuffled inserts in {:.2?} ({:.0} inserts/s)",
+        count,
+        elapsed,
+        count as f64 / elapsed.as_secs_f64()
+    );
+
+    let started = Instant::now();
+    let window = count as u64 * 60_000 / 100;
+    let matches = tree.get_available_offers(0, 3, window, 2 * window).count();
+    println!(
+        "IndexTree::get_available_offers: {} matches in {:.2?}",
+        matches,
+        started.elapsed()
+    );
+
+    let baseline = count.min(VEC_BASELINE_OFFERS);
+    let mut sorted: Vec<(u64, u32)> = Vec::new();
+    let started = Instant::now();
+    for (idx, &start_date) in dates[..baseline].iter().enumerate() {
+        let pos = sorted.partition_point(|(other, _)| *other < start_date);
+        sorted.insert(pos, (start_date, idx as u32));
+    }
+    let elapsed = started.elapsed();
+    black_box(&sorted);
+    println!(
+        "Vec::insert baseline: {} shuffled inserts in {:.2?} ({:.0} inserts/s)",
+        baseline,
+        elapsed,
+        baseline as f64 / elapsed.as_secs_f64()
+    );
+}
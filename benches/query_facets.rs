@@ -0,0 +1,133 @@
+//! Searches region 0, which covers every offer, with all facets, with
+//! `facets=priceRanges` and with `facets=none`, and reports the time per
+//! search for each. Then counts the offers of region 0 with the car type,
+//! vollkasko and seat facets, which the index block summaries answer.
+//!
+//! Run with `cargo bench --bench query_facets`. `BENCH_OFFERS` overrides the
+//! number of offers (default 2M), `BENCH_SEARCHES` the searches per variant
+//! (default 20).
+
+use clueless::currency::Currency;
+use clueless::db_manager::DBManager;
+use clueless::db_models::Offer;
+use clueless::json_models::{RequestOffer, SortOrder};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const DAY_MS: u64 = 1000 * 60 * 60 * 24;
+const CAR_TYPES: [&str; 4] = ["small", "sports", "luxury", "family"];
+
+fn env_or(key: &str, default: usize) -> usize {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn offer(idx: u32, mut state: u64) -> Offer {
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let start_date = next() % 30 * DAY_MS;
+    Offer {
+        idx,
+        id: format!("offer-{}", idx),
+        data: String::new(),
+        most_specific_region_id: 21 + (next() % 100) as u32,
+        start_date,
+        end_date: start_date + 3 * DAY_MS,
+        number_seats: 2 + (next() % 7) as u32,
+        price: (next() % 100_000) as u32,
+        currency: Currency::EUR,
+        car_type: CAR_TYPES[next() as usize % CAR_TYPES.len()]
+            .parse()
+            .unwrap(),
+        has_vollkasko: next() % 2 == 0,
+        free_kilometers: (next() % 2_000) as u32,
+        expires_at: u64::MAX,
+        flexible: false,
+        attributes: Box::default(),
+    }
+}
+
+fn search(facets: Option<&str>) -> RequestOffer {
+    RequestOffer {
+        region_ids: vec![0],
+        excluded_region_ids: Vec::new(),
+        time_range_start: 0,
+        time_range_end: 40 * DAY_MS,
+        number_days: 3,
+        sort_order: SortOrder::PriceAsc,
+        page: 0,
+        page_size: 100,
+        price_range_width: 1_000,
+        min_free_kilometer_width: 100,
+        min_number_seats: Some(4),
+        min_price: None,
+        max_price: None,
+        car_type: None,
+        only_vollkasko: None,
+        min_free_kilometer: None,
+        currency: None,
+        attribute_filters: Vec::new(),
+        price_buckets: None,
+        free_kilometer_buckets: None,
+        stats: None,
+        facets: facets.map(str::to_string),
+        include_region: false,
+        near: None,
+        radius_km: None,
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let count = env_or("BENCH_OFFERS", 2_000_000);
+    let searches = env_or("BENCH_SEARCHES", 20);
+
+    let manager = DBManager::new();
+    let offers = (0..count as u32)
+        .map(|idx| {
+            offer(
+                idx,
+                0x9e37_79b9_7f4a_7c15 ^ ((idx as u64 + 1) * 0x2545_f491),
+            )
+        })
+        .collect();
+    let inserted = manager.insert_offers(offers).await;
+    println!("{} offers inserted", inserted);
+
+    for (label, facets) in [
+        ("all facets", None),
+        ("facets=priceRanges", Some("priceRanges")),
+        ("facets=none", Some("none")),
+    ] {
+        let mut total = Duration::ZERO;
+        for _ in 0..searches {
+            let started = Instant::now();
+            black_box(manager.query_for(search(facets)).await.unwrap());
+            total += started.elapsed();
+        }
+        println!(
+            "DBManager::query_for region 0, {}: {:.2?} per search",
+            label,
+            total / searches.max(1) as u32
+        );
+    }
+
+    let mut total = Duration::ZERO;
+    for _ in 0..searches {
+        let mut count = search(Some("carTypeCounts,vollkaskoCount,seatsCount"));
+        count.min_number_seats = None;
+        let started = Instant::now();
+        black_box(manager.count_for(count).await.unwrap());
+        total += started.elapsed();
+    }
+    println!(
+        "DBManager::count_for region 0, unfiltered: {:.2?} per search",
+        total / searches.max(1) as u32
+    );
+}
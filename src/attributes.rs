@@ -0,0 +1,417 @@
+use crate::config::CONFIG;
+use crate::json_models::{AttributeFacet, AttributeRange, AttributeValueCount};
+use crate::range_facets::RangeFacet;
+use crate::GenericError;
+use fxhash::{FxHashMap, FxHashSet};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Stored for attributes an offer does not set. Never matches a filter.
+pub const MISSING: u32 = u32::MAX;
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AttributeKind {
+    /// One of a fixed list of strings, stored as its position in `values`.
+    Categorical {
+        values: Vec<String>,
+    },
+    /// A non-negative integer.
+    Numeric,
+    Boolean,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AttributeDef {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: AttributeKind,
+}
+
+/// Offer attributes beyond the built-in fields, declared in the JSON file
+/// named by `CLUELESS_ATTRIBUTE_SCHEMA`:
+///
+/// ```json
+/// {"attributes": [
+///   {"name": "fuel", "type": "categorical", "values": ["petrol", "diesel", "electric"]},
+///   {"name": "doors", "type": "numeric"},
+///   {"name": "automatic", "type": "boolean"}
+/// ]}
+/// ```
+///
+/// Every offer stores one `u32` slot per declared attribute, in declaration order.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttributeSchema {
+    pub attributes: Vec<AttributeDef>,
+}
+
+pub static SCHEMA: Lazy<AttributeSchema> = Lazy::new(|| match &CONFIG.attribute_schema_path {
+    Some(path) => AttributeSchema::load(path)
+        .unwrap_or_else(|err| panic!("Invalid attribute schema {}: {}", path.display(), err)),
+    None => AttributeSchema::default(),
+});
+
+/// Filters and facet settings of a search, parsed from its `attr.*` parameters.
+#[derive(Debug, Default)]
+pub struct AttributeQuery {
+    pub filters: Vec<AttributeFilter>,
+    /// Range facet width per numeric attribute slot.
+    widths: Vec<u32>,
+}
+
+/// Matches offers whose attribute value lies in `min..=max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttributeFilter {
+    pub slot: usize,
+    min: u32,
+    max: u32,
+}
+
+impl AttributeFilter {
+    #[inline(always)]
+    pub fn matches(&self, attributes: &[u32]) -> bool {
+        let value = attribute(attributes, self.slot);
+        value != MISSING && self.min <= value && value <= self.max
+    }
+}
+
+#[inline(always)]
+fn attribute(attributes: &[u32], slot: usize) -> u32 {
+    attributes.get(slot).copied().unwrap_or(MISSING)
+}
+
+impl AttributeSchema {
+    pub fn from_json(json: &[u8]) -> Result<Self, GenericError> {
+        let schema: AttributeSchema = serde_json::from_slice(json)?;
+        let mut names = FxHashSet::default();
+        for attribute in &schema.attributes {
+            if attribute.name.is_empty() || attribute.name.contains(['.', '&', '=']) {
+                return Err(format!("Invalid attribute name '{}'", attribute.name).into());
+            }
+            if !names.insert(attribute.name.as_str()) {
+                return Err(format!("Duplicate attribute '{}'", attribute.name).into());
+            }
+        }
+        Ok(schema)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, GenericError> {
+        Self::from_json(&std::fs::read(path)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.attributes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attributes.is_empty()
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.attributes
+            .iter()
+            .position(|attribute| attribute.name == name)
+    }
+
+    /// Encodes the `attributes` object of an uploaded offer.
+    pub fn encode(
+        &self,
+        values: &FxHashMap<String, serde_json::Value>,
+    ) -> Result<Box<[u32]>, &'static str> {
+        if values.is_empty() && self.is_empty() {
+            return Ok(Box::default());
+        }
+        let mut encoded = vec![MISSING; self.len()];
+        for (name, value) in values {
+            let slot = self.position(name).ok_or("Unknown attribute")?;
+            encoded[slot] = match (&self.attributes[slot].kind, value) {
+                (_, serde_json::Value::Null) => MISSING,
+                (AttributeKind::Categorical { .. }, serde_json::Value::String(value)) => {
+                    self.encode_str(slot, value)?
+                }
+                (AttributeKind::Numeric, serde_json::Value::Number(value)) => value
+                    .as_u64()
+                    .and_then(|value| u32::try_from(value).ok())
+                    .filter(|&value| value != MISSING)
+                    .ok_or("Invalid numeric attribute")?,
+                (AttributeKind::Boolean, serde_json::Value::Bool(value)) => *value as u32,
+                _ => return Err("Invalid attribute value"),
+            };
+        }
+        Ok(encoded.into_boxed_slice())
+    }
+
+    /// Encodes an attribute value given as text, as in CSV uploads and
+    /// search parameters.
+    pub fn encode_str(&self, slot: usize, value: &str) -> Result<u32, &'static str> {
+        match &self.attributes[slot].kind {
+            AttributeKind::Categorical { values } => values
+                .iter()
+                .position(|known| known == value)
+                .map(|position| position as u32)
+                .ok_or("Unknown attribute value"),
+            AttributeKind::Numeric => value
+                .parse::<u32>()
+                .ok()
+                .filter(|&value| value != MISSING)
+                .ok_or("Invalid numeric attribute"),
+            AttributeKind::Boolean => value
+                .parse::<bool>()
+                .map(|value| value as u32)
+                .map_err(|_| "Invalid boolean attribute"),
+        }
+    }
+
+    /// Parses the `attr.*` search parameters, given without their prefix:
+    /// `fuel=electric` filters by value, `doors.min=4` and `doors.max=5` bound
+    /// numeric attributes, and `doors.width=2` sets their facet range width.
+    /// An attribute can be filtered by value only once.
+    pub fn compile_query(
+        &self,
+        params: &[(String, String)],
+    ) -> Result<AttributeQuery, &'static str> {
+        let mut filters: Vec<AttributeFilter> = Vec::new();
+        let mut widths = vec![1; self.len()];
+        let mut by_value = vec![false; self.len()];
+        for (key, value) in params {
+            let (name, modifier) = match key.split_once('.') {
+                Some((name, modifier)) => (name, Some(modifier)),
+                None => (key.as_str(), None),
+            };
+            let slot = self.position(name).ok_or("Unknown attribute")?;
+            let numeric = self.attributes[slot].kind == AttributeKind::Numeric;
+            let (min, max) = match modifier {
+                None => {
+                    if std::mem::replace(&mut by_value[slot], true) {
+                        return Err("Repeated attribute filter");
+                    }
+                    let value = self.encode_str(slot, value)?;
+                    (value, value)
+                }
+                Some("min") if numeric => (self.encode_str(slot, value)?, MISSING - 1),
+                Some("max") if numeric => (0, self.encode_str(slot, value)?),
+                Some("width") if numeric => {
+                    widths[slot] = value
+                        .parse()
+                        .ok()
+                        .filter(|&width| width > 0)
+                        .ok_or("Invalid attribute facet width")?;
+                    continue;
+                }
+                _ => return Err("Unknown attribute parameter"),
+            };
+            match filters.iter_mut().find(|filter| filter.slot == slot) {
+                Some(filter) => {
+                    filter.min = filter.min.max(min);
+                    filter.max = filter.max.min(max);
+                }
+                None => filters.push(AttributeFilter { slot, min, max }),
+            }
+        }
+        Ok(AttributeQuery { filters, widths })
+    }
+}
+
+impl AttributeQuery {
+    /// Empty facet counters, one per attribute slot. Numeric attributes are
+    /// bucketed by their facet width, the others counted by value.
+    pub fn counters(&self) -> Vec<RangeFacet> {
+        self.widths
+            .iter()
+            .map(|&width| RangeFacet::new(width, None, CONFIG.max_facet_buckets).unwrap())
+            .collect()
+    }
+
+    /// Counts an offer towards the facet of one attribute.
+    #[inline(always)]
+    pub fn count(&self, counts: &mut [RangeFacet], slot: usize, attributes: &[u32]) {
+        let value = attribute(attributes, slot);
+        if value != MISSING {
+            counts[slot].count(value);
+        }
+    }
+
+    /// Counts an offer towards the facets of all attributes.
+    #[inline(always)]
+    pub fn count_all(&self, counts: &mut [RangeFacet], attributes: &[u32]) {
+        for slot in 0..counts.len() {
+            self.count(counts, slot, attributes);
+        }
+    }
+
+    /// Numeric facets are capped at `CONFIG.max_facet_buckets` buckets like
+    /// the built-in range facets.
+    pub fn facets(&self, schema: &AttributeSchema, counts: Vec<RangeFacet>) -> Vec<AttributeFacet> {
+        schema
+            .attributes
+            .iter()
+            .zip(counts)
+            .map(|(attribute, counts)| {
+                let mut facet = AttributeFacet {
+                    name: attribute.name.clone(),
+                    values: Vec::new(),
+                    ranges: Vec::new(),
+                };
+                match &attribute.kind {
+                    AttributeKind::Categorical { values } => {
+                        facet.values = counts
+                            .into_ranges(usize::MAX)
+                            .into_iter()
+                            .map(|(value, _, count)| AttributeValueCount {
+                                value: values[value as usize].clone(),
+                                count,
+                            })
+                            .collect()
+                    }
+                    AttributeKind::Boolean => {
+                        facet.values = counts
+                            .into_ranges(usize::MAX)
+                            .into_iter()
+                            .map(|(value, _, count)| AttributeValueCount {
+                                value: (value != 0).to_string(),
+                                count,
+                            })
+                            .collect()
+                    }
+                    AttributeKind::Numeric => {
+                        facet.ranges = counts
+                            .into_ranges(CONFIG.max_facet_buckets)
+                            .into_iter()
+                            .map(|(start, end, count)| AttributeRange { start, end, count })
+                            .collect()
+                    }
+                }
+                facet
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_schema() -> AttributeSchema {
+        AttributeSchema::from_json(
+            br#"{"attributes": [
+                {"name": "fuel", "type": "categorical", "values": ["petrol", "diesel", "electric"]},
+                {"name": "doors", "type": "numeric"},
+                {"name": "automatic", "type": "boolean"}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn encodes_uploaded_attributes() {
+        let schema = test_schema();
+        let values: FxHashMap<String, serde_json::Value> =
+            serde_json::from_str(r#"{"fuel": "electric", "automatic": true}"#).unwrap();
+        assert_eq!(&*schema.encode(&values).unwrap(), [2, MISSING, 1]);
+
+        for invalid in [
+            r#"{"fuel": "steam"}"#,
+            r#"{"doors": -1}"#,
+            r#"{"doors": "4"}"#,
+            r#"{"colour": "red"}"#,
+        ] {
+            let values: FxHashMap<String, serde_json::Value> =
+                serde_json::from_str(invalid).unwrap();
+            assert!(schema.encode(&values).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn compiles_filters_and_counts_facets() {
+        let schema = test_schema();
+        let params = [
+            ("fuel", "electric"),
+            ("doors.min", "3"),
+            ("doors.max", "5"),
+            ("doors.width", "2"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+        let query = schema.compile_query(&params).unwrap();
+        assert_eq!(query.filters.len(), 2);
+        let matches = |attributes: &[u32]| query.filters.iter().all(|f| f.matches(attributes));
+        assert!(matches(&[2, 4, 0]));
+        assert!(!matches(&[2, 6, 0]));
+        assert!(!matches(&[1, 4, 0]));
+        assert!(!matches(&[2, MISSING, 0]));
+
+        let mut counts = query.counters();
+        for attributes in [[2, 4, 1], [2, 5, 0], [0, 3, MISSING]] {
+            query.count_all(&mut counts, &attributes);
+        }
+        let facets = query.facets(&schema, counts);
+        let values: Vec<_> = facets[0]
+            .values
+            .iter()
+            .map(|count| (count.value.as_str(), count.count))
+            .collect();
+        assert_eq!(values, [("petrol", 1), ("electric", 2)]);
+        let ranges: Vec<_> = facets[1]
+            .ranges
+            .iter()
+            .map(|range| (range.start, range.end, range.count))
+            .collect();
+        assert_eq!(ranges, [(2, 4, 1), (4, 6, 2)]);
+        assert_eq!(facets[2].values.len(), 2);
+
+        for invalid in [
+            ("colour", "red"),
+            ("fuel", "steam"),
+            ("fuel.min", "1"),
+            ("doors.width", "0"),
+        ] {
+            let params = [(invalid.0.to_string(), invalid.1.to_string())];
+            assert!(schema.compile_query(&params).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn caps_numeric_facet_buckets() {
+        let schema = test_schema();
+        let query = schema.compile_query(&[]).unwrap();
+        let mut counts = query.counters();
+        let values = CONFIG.max_facet_buckets as u32 + 5;
+        for doors in 0..values {
+            query.count_all(&mut counts, &[MISSING, doors, MISSING]);
+        }
+        let ranges = &query.facets(&schema, counts)[1].ranges;
+        assert_eq!(ranges.len(), CONFIG.max_facet_buckets);
+        let last = ranges.last().unwrap();
+        assert_eq!(last.end, u32::MAX);
+        assert_eq!(ranges.iter().map(|range| range.count).sum::<u32>(), values);
+    }
+
+    #[test]
+    fn rejects_repeated_value_filters() {
+        let schema = test_schema();
+        let compile = |params: &[(&str, &str)]| {
+            let params: Vec<_> = params
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            schema.compile_query(&params)
+        };
+        for repeated in [
+            [("fuel", "petrol"), ("fuel", "electric")],
+            [("fuel", "petrol"), ("fuel", "petrol")],
+            [("automatic", "true"), ("automatic", "false")],
+            [("doors", "4"), ("doors", "5")],
+        ] {
+            assert!(compile(&repeated).is_err(), "{:?}", repeated);
+        }
+        // Bounds still narrow a value filter or each other.
+        assert_eq!(
+            compile(&[("doors", "4"), ("doors.min", "3"), ("doors.max", "5")])
+                .unwrap()
+                .filters
+                .len(),
+            1
+        );
+        assert!(compile(&[("doors.min", "3"), ("doors.min", "4")]).is_ok());
+    }
+}
@@ -0,0 +1,105 @@
+//! Offline bulk loader: parses offer files and writes a snapshot that the
+//! server boots from when started with `CLUELESS_SNAPSHOT=<path>`.
+//!
+//! Usage: `clueless-load [--output <snapshot>] <file.json|file.ndjson|file.csv>...`
+
+use clueless::db_manager::DenseStore;
+use clueless::db_models::Offer;
+use clueless::index_tree::{IndexTree, ROOT_REGION};
+use clueless::ingest::OfferDecoder;
+use clueless::{snapshot, GenericError};
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const USAGE: &str =
+    "Usage: clueless-load [--output <snapshot>] <file.json|file.ndjson|file.csv>...";
+
+struct LoadedFile {
+    offers: Vec<Offer>,
+    rejected: u32,
+}
+
+fn main() -> Result<(), GenericError> {
+    let mut output = PathBuf::from("offers.snapshot");
+    let mut inputs = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = args.next().ok_or(USAGE)?.into(),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+    if inputs.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let started = Instant::now();
+    let files = inputs
+        .par_iter()
+        .map(|path| load_file(path).map_err(|err| format!("{}: {}", path.display(), err)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut index_tree = IndexTree::populate_with_regions(&ROOT_REGION);
+    let mut rejected = 0;
+    let mut all = Vec::with_capacity(files.iter().map(|file| file.offers.len()).sum());
+    for file in files {
+        rejected += file.rejected;
+        for mut offer in file.offers {
+            if !index_tree.contains_region(offer.most_specific_region_id as u8) {
+                rejected += 1;
+                continue;
+            }
+            offer.idx = all.len() as u32;
+            all.push(offer);
+        }
+    }
+    println!(
+        "Parsed {} offers ({} rejected) in {:.2?}",
+        all.len(),
+        rejected,
+        started.elapsed()
+    );
+
+    index_tree.bulk_insert(&all);
+    let dense_store = DenseStore::from_offers(all);
+    snapshot::write_snapshot(&output, &dense_store, &index_tree)?;
+    println!("Wrote {} in {:.2?}", output.display(), started.elapsed());
+    Ok(())
+}
+
+fn load_file(path: &Path) -> Result<LoadedFile, GenericError> {
+    let content_type = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => "application/json",
+        Some("ndjson") | Some("jsonl") => "application/x-ndjson",
+        Some("csv") => "text/csv",
+        _ => return Err("Unknown file extension, expected .json, .ndjson or .csv".into()),
+    };
+    let mut decoder = OfferDecoder::for_content_type(Some(content_type)).unwrap();
+    let mut loaded = LoadedFile {
+        offers: Vec::new(),
+        rejected: 0,
+    };
+    let mut on_offer = |decoded: Result<Offer, &'static str>| match decoded {
+        Ok(offer) => loaded.offers.push(offer),
+        Err(_) => loaded.rejected += 1,
+    };
+
+    let mut file = File::open(path)?;
+    let mut chunk = vec![0; 1 << 20];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        decoder.feed(&chunk[..read], &mut on_offer)?;
+    }
+    decoder.finish(&mut on_offer)?;
+    Ok(loaded)
+}
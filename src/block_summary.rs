@@ -0,0 +1,136 @@
+use crate::db_models::Offer;
+use crate::index_tree::IndexTreeOffer;
+
+/// Entries per summarized block of a sorted run.
+pub(crate) const BLOCK_SIZE: usize = 1024;
+
+/// The fields of an offer the unfiltered facets count, packed to fit into
+/// the padding of an index entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FacetKey {
+    car_type: u8,
+    has_vollkasko: bool,
+    /// Saturates at `u16::MAX`; blocks holding such an offer are not summed.
+    number_seats: u16,
+}
+
+impl From<&Offer> for FacetKey {
+    fn from(offer: &Offer) -> Self {
+        FacetKey {
+            car_type: offer.car_type.id(),
+            has_vollkasko: offer.has_vollkasko,
+            number_seats: offer.number_seats.min(u16::MAX as u32) as u16,
+        }
+    }
+}
+
+/// Car type, vollkasko and seat counts over a set of offers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct FacetSummary {
+    pub(crate) len: u32,
+    pub(crate) vollkasko: u32,
+    /// Indexed by car type id.
+    pub(crate) car_types: Vec<u32>,
+    /// `(number_seats, count)`, ordered by seats.
+    pub(crate) seats: Vec<(u32, u32)>,
+}
+
+impl FacetSummary {
+    pub(crate) fn add_offer(&mut self, offer: &Offer) {
+        self.add(
+            offer.car_type.id(),
+            offer.has_vollkasko,
+            offer.number_seats,
+            1,
+        );
+    }
+
+    pub(crate) fn merge(&mut self, other: &FacetSummary) {
+        self.len += other.len;
+        self.vollkasko += other.vollkasko;
+        if self.car_types.len() < other.car_types.len() {
+            self.car_types.resize(other.car_types.len(), 0);
+        }
+        for (count, other) in self.car_types.iter_mut().zip(&other.car_types) {
+            *count += other;
+        }
+        for &(number_seats, count) in &other.seats {
+            self.add_seats(number_seats, count);
+        }
+    }
+
+    fn add(&mut self, car_type: u8, has_vollkasko: bool, number_seats: u32, count: u32) {
+        self.len += count;
+        if has_vollkasko {
+            self.vollkasko += count;
+        }
+        let car_type = car_type as usize;
+        if self.car_types.len() <= car_type {
+            self.car_types.resize(car_type + 1, 0);
+        }
+        self.car_types[car_type] += count;
+        self.add_seats(number_seats, count);
+    }
+
+    fn add_seats(&mut self, number_seats: u32, count: u32) {
+        match self
+            .seats
+            .binary_search_by_key(&number_seats, |&(seats, _)| seats)
+        {
+            Ok(position) => self.seats[position].1 += count,
+            Err(position) => self.seats.insert(position, (number_seats, count)),
+        }
+    }
+}
+
+/// Summary of up to [`BLOCK_SIZE`] consecutive entries of a sorted run.
+#[derive(Debug, Clone)]
+pub(crate) struct Block {
+    pub(crate) min_start: u64,
+    pub(crate) max_start: u64,
+    pub(crate) max_end: u64,
+    /// False if a facet key of the block saturated, so its counts are off.
+    pub(crate) exact: bool,
+    pub(crate) facets: FacetSummary,
+}
+
+/// Summarizes a run, which must be sorted by start date, block by block.
+pub(crate) fn summarize_blocks(run: &[IndexTreeOffer]) -> Vec<Block> {
+    run.chunks(BLOCK_SIZE)
+        .map(|entries| {
+            let mut facets = FacetSummary::default();
+            for entry in entries {
+                let key = entry.facet_key;
+                facets.add(key.car_type, key.has_vollkasko, key.number_seats as u32, 1);
+            }
+            Block {
+                min_start: entries[0].start_date,
+                max_start: entries[entries.len() - 1].start_date,
+                max_end: entries.iter().map(|entry| entry.end_date).max().unwrap(),
+                exact: entries
+                    .iter()
+                    .all(|entry| entry.facet_key.number_seats < u16::MAX),
+                facets,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_counts_by_key() {
+        let mut summary = FacetSummary::default();
+        summary.add(2, true, 5, 1);
+        summary.add(0, false, 2, 1);
+        let mut other = FacetSummary::default();
+        other.add(0, true, 5, 3);
+        summary.merge(&other);
+        assert_eq!(summary.len, 5);
+        assert_eq!(summary.vollkasko, 4);
+        assert_eq!(summary.car_types, [4, 0, 1]);
+        assert_eq!(summary.seats, [(2, 1), (5, 4)]);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::config::CONFIG;
+use crate::GenericError;
+use fxhash::FxHashSet;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A car type, stored as its dense position in [`CAR_TYPES`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CarType(u8);
+
+impl CarType {
+    /// Stands in for a name that is not configured; never matches an offer.
+    pub const UNKNOWN: CarType = CarType(u8::MAX);
+
+    pub fn from_id(id: u8) -> Self {
+        CarType(id)
+    }
+
+    #[inline(always)]
+    pub fn id(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_known(self) -> bool {
+        (self.0 as usize) < CAR_TYPES.len()
+    }
+}
+
+impl FromStr for CarType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CAR_TYPES.get(s).ok_or(())
+    }
+}
+
+impl TryFrom<String> for CarType {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse().map_err(|_| "Unknown car type")
+    }
+}
+
+impl From<CarType> for String {
+    fn from(car_type: CarType) -> Self {
+        car_type.to_string()
+    }
+}
+
+impl fmt::Display for CarType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(CAR_TYPES.name(*self).unwrap_or("unknown"))
+    }
+}
+
+/// The car types offers may have, configured as a comma-separated list in
+/// `CLUELESS_CAR_TYPES`. Each type's id is its position in the list, so new
+/// types must be appended to keep the ids of existing ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CarTypes {
+    names: Vec<String>,
+}
+
+pub static CAR_TYPES: Lazy<CarTypes> = Lazy::new(|| {
+    CarTypes::new(CONFIG.car_types.clone())
+        .unwrap_or_else(|err| panic!("Invalid CLUELESS_CAR_TYPES: {}", err))
+});
+
+impl CarTypes {
+    pub fn new(names: Vec<String>) -> Result<Self, GenericError> {
+        if names.is_empty() || names.len() >= CarType::UNKNOWN.0 as usize {
+            return Err(format!("Expected 1 to {} car types", CarType::UNKNOWN.0 - 1).into());
+        }
+        let mut seen = FxHashSet::default();
+        for name in &names {
+            if name.is_empty() || name.contains([',', '&', '=']) {
+                return Err(format!("Invalid car type '{}'", name).into());
+            }
+            if !seen.insert(name.as_str()) {
+                return Err(format!("Duplicate car type '{}'", name).into());
+            }
+        }
+        Ok(Self { names })
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn get(&self, name: &str) -> Option<CarType> {
+        self.names
+            .iter()
+            .position(|known| known == name)
+            .map(|position| CarType(position as u8))
+    }
+
+    pub fn name(&self, car_type: CarType) -> Option<&str> {
+        self.names.get(car_type.0 as usize).map(String::as_str)
+    }
+
+    /// Turns per-id counts into the `carTypeCounts` facet, listing every
+    /// configured type.
+    pub fn facet(&self, counts: &[u32]) -> BTreeMap<String, u32> {
+        self.names
+            .iter()
+            .zip(counts.iter().chain(std::iter::repeat(&0)))
+            .map(|(name, count)| (name.clone(), *count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn car_types(names: &[&str]) -> Result<CarTypes, GenericError> {
+        CarTypes::new(names.iter().map(|name| name.to_string()).collect())
+    }
+
+    #[test]
+    fn assigns_dense_ids_in_configured_order() {
+        let car_types = car_types(&["small", "van", "electric"]).unwrap();
+        let van = car_types.get("van").unwrap();
+        assert_eq!(van.id(), 1);
+        assert_eq!(car_types.name(van), Some("van"));
+        assert_eq!(car_types.get("suv"), None);
+        assert_eq!(car_types.name(CarType::UNKNOWN), None);
+
+        let facet = car_types.facet(&[0, 3]);
+        assert_eq!(facet.len(), 3);
+        assert_eq!(facet["van"], 3);
+        assert_eq!(facet["electric"], 0);
+    }
+
+    #[test]
+    fn rejects_invalid_configurations() {
+        assert!(car_types(&[]).is_err());
+        assert!(car_types(&["small", "small"]).is_err());
+        assert!(car_types(&["small", ""]).is_err());
+        assert!(car_types(&["a=b"]).is_err());
+    }
+
+    #[test]
+    fn default_types_parse() {
+        assert!("family".parse::<CarType>().unwrap().is_known());
+        assert!("hovercraft".parse::<CarType>().is_err());
+        assert!(!CarType::UNKNOWN.is_known());
+    }
+}
@@ -0,0 +1,85 @@
+use crate::currency::Currency;
+use once_cell::sync::Lazy;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Runtime settings, read once from `CLUELESS_*` environment variables.
+#[derive(Debug)]
+pub struct Config {
+    /// Largest accepted `POST /api/offers` body, in bytes.
+    pub max_body_bytes: usize,
+    /// Number of parsed offers inserted per write-lock acquisition while ingesting.
+    pub insert_batch_size: usize,
+    /// Snapshot written by `clueless-load` to boot from, if any.
+    pub snapshot_path: Option<PathBuf>,
+    /// TTL applied to every offer from the time it is ingested. Offers may
+    /// carry a shorter `ttlSeconds` of their own.
+    pub offer_ttl_secs: Option<u64>,
+    /// Also evict offers whose start date has already passed.
+    pub evict_started_offers: bool,
+    /// Seconds between eviction passes; 0 disables the eviction task.
+    pub eviction_interval_secs: u64,
+    /// Fraction of removed store slots at which the store is compacted.
+    pub compaction_threshold: f64,
+    /// JSON file of exchange rates, reloadable via `POST /admin/rates/reload`.
+    pub rates_path: Option<PathBuf>,
+    /// JSON file declaring extra offer attributes, see [`crate::attributes`].
+    pub attribute_schema_path: Option<PathBuf>,
+    /// Currency of uploaded offers that do not name one.
+    pub default_currency: Currency,
+    /// Default duration of `POST /api/offers/{id}/hold`.
+    pub hold_ttl_secs: u64,
+    /// Offers the dense store reserves room for at startup.
+    pub initial_store_capacity: usize,
+    /// Memory the store and index may use before uploads are refused.
+    pub memory_limit_bytes: Option<usize>,
+    /// Car type names, see [`crate::car_types`].
+    pub car_types: Vec<String>,
+    /// Most buckets a range facet such as `priceRanges` may return.
+    pub max_facet_buckets: usize,
+    /// Searches kept by the query cache; 0 disables it.
+    pub query_cache_entries: usize,
+    /// Index entries a search has to walk before it is split across threads.
+    pub parallel_search_threshold: usize,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            max_body_bytes: env_or("CLUELESS_MAX_BODY_BYTES", 4 << 30),
+            insert_batch_size: env_or("CLUELESS_INSERT_BATCH_SIZE", 16_384).max(1),
+            snapshot_path: std::env::var_os("CLUELESS_SNAPSHOT").map(PathBuf::from),
+            offer_ttl_secs: std::env::var("CLUELESS_OFFER_TTL_SECS")
+                .ok()
+                .and_then(|value| value.parse().ok()),
+            evict_started_offers: env_or("CLUELESS_EVICT_STARTED_OFFERS", false),
+            eviction_interval_secs: env_or("CLUELESS_EVICTION_INTERVAL_SECS", 60),
+            compaction_threshold: env_or("CLUELESS_COMPACTION_THRESHOLD", 0.25),
+            rates_path: std::env::var_os("CLUELESS_RATES_FILE").map(PathBuf::from),
+            attribute_schema_path: std::env::var_os("CLUELESS_ATTRIBUTE_SCHEMA").map(PathBuf::from),
+            default_currency: env_or("CLUELESS_DEFAULT_CURRENCY", Currency::EUR),
+            hold_ttl_secs: env_or("CLUELESS_HOLD_TTL_SECS", 600),
+            initial_store_capacity: env_or("CLUELESS_INITIAL_STORE_CAPACITY", 1 << 20),
+            memory_limit_bytes: std::env::var("CLUELESS_MEMORY_LIMIT_BYTES")
+                .ok()
+                .and_then(|value| value.parse().ok()),
+            car_types: std::env::var("CLUELESS_CAR_TYPES")
+                .unwrap_or_else(|_| "small,sports,luxury,family".to_string())
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .collect(),
+            max_facet_buckets: env_or("CLUELESS_MAX_FACET_BUCKETS", 1000).max(1),
+            query_cache_entries: env_or("CLUELESS_QUERY_CACHE_ENTRIES", 1024),
+            parallel_search_threshold: env_or("CLUELESS_PARALLEL_SEARCH_THRESHOLD", 50_000),
+        }
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+pub static CONFIG: Lazy<Config> = Lazy::new(Config::from_env);
@@ -0,0 +1,171 @@
+use crate::GenericError;
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// An ISO 4217 currency code such as `EUR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const EUR: Currency = Currency(*b"EUR");
+    /// Stands in for a code that failed to parse; never has a rate.
+    pub const INVALID: Currency = Currency(*b"???");
+
+    pub fn is_valid(&self) -> bool {
+        self.0.iter().all(u8::is_ascii_uppercase)
+    }
+
+    pub fn as_bytes(&self) -> [u8; 3] {
+        self.0
+    }
+
+    pub fn from_bytes(bytes: [u8; 3]) -> Option<Self> {
+        let currency = Currency(bytes);
+        currency.is_valid().then_some(currency)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes: [u8; 3] = s.as_bytes().try_into().map_err(|_| ())?;
+        Currency::from_bytes(bytes).ok_or(())
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse().map_err(|_| "Invalid currency code")
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.to_string()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Codes are validated to be ASCII on construction.
+        f.write_str(std::str::from_utf8(&self.0).unwrap_or("???"))
+    }
+}
+
+/// Exchange rates, each given as units of the currency per unit of a common
+/// base currency. Loaded from a JSON object such as `{"EUR": 1.0, "USD": 1.08}`.
+#[derive(Debug, Default, Clone)]
+pub struct ExchangeRates {
+    rates: FxHashMap<Currency, f64>,
+}
+
+impl ExchangeRates {
+    pub fn from_json(json: &[u8]) -> Result<Self, GenericError> {
+        let rates: FxHashMap<Currency, f64> = sonic_rs::from_slice(json)?;
+        if let Some((currency, _)) = rates
+            .iter()
+            .find(|(_, rate)| !(rate.is_finite() && **rate > 0.0))
+        {
+            return Err(format!("Invalid exchange rate for {}", currency).into());
+        }
+        Ok(Self { rates })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, GenericError> {
+        Self::from_json(&std::fs::read(path)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    /// The currencies with a rate, sorted by code.
+    pub fn currencies(&self) -> Vec<Currency> {
+        let mut currencies: Vec<_> = self.rates.keys().copied().collect();
+        currencies.sort_unstable_by_key(Currency::as_bytes);
+        currencies
+    }
+
+    /// Factor turning an amount in `from` into one in `to`, if both are known.
+    /// Converting a currency into itself never needs a rate.
+    pub fn factor(&self, from: Currency, to: Currency) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+        Some(self.rates.get(&to)? / self.rates.get(&from)?)
+    }
+}
+
+/// Converts prices of any currency into one target currency, caching the
+/// factor per source currency for the duration of a search.
+pub struct PriceConverter<'a> {
+    rates: &'a ExchangeRates,
+    target: Currency,
+    factors: FxHashMap<Currency, Option<f64>>,
+}
+
+impl<'a> PriceConverter<'a> {
+    pub fn new(rates: &'a ExchangeRates, target: Currency) -> Self {
+        Self {
+            rates,
+            target,
+            factors: FxHashMap::default(),
+        }
+    }
+
+    /// The price in the target currency, rounded to whole units, or `None`
+    /// if there is no rate for `currency`.
+    #[inline(always)]
+    pub fn convert(&mut self, price: u32, currency: Currency) -> Option<u32> {
+        if currency == self.target {
+            return Some(price);
+        }
+        let factor = (*self
+            .factors
+            .entry(currency)
+            .or_insert_with(|| self.rates.factor(currency, self.target)))?;
+        Some((price as f64 * factor).round() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_through_the_common_base() {
+        let rates = ExchangeRates::from_json(br#"{"EUR": 1.0, "USD": 1.25, "JPY": 160}"#).unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+        let jpy: Currency = "JPY".parse().unwrap();
+        let chf: Currency = "CHF".parse().unwrap();
+
+        let mut converter = PriceConverter::new(&rates, usd);
+        assert_eq!(converter.convert(1000, Currency::EUR), Some(1250));
+        assert_eq!(converter.convert(16_000, jpy), Some(125));
+        assert_eq!(converter.convert(1000, usd), Some(1000));
+        assert_eq!(converter.convert(1000, chf), None);
+        // Same-currency searches work without any rates.
+        let empty = ExchangeRates::default();
+        assert_eq!(PriceConverter::new(&empty, chf).convert(7, chf), Some(7));
+    }
+
+    #[test]
+    fn rejects_invalid_codes_and_rates() {
+        assert!("usd".parse::<Currency>().is_err());
+        assert!("EURO".parse::<Currency>().is_err());
+        assert!(!Currency::INVALID.is_valid());
+        assert!(ExchangeRates::from_json(br#"{"EUR": 0}"#).is_err());
+        assert!(ExchangeRates::from_json(br#"{"eur": 1}"#).is_err());
+    }
+}
@@ -1,25 +1,53 @@
-use std::cmp::Reverse;
-use crate::db_models::{ Offer};
-use crate::index_tree::{IndexTree, ROOT_REGION};
+use crate::attributes::{AttributeQuery, AttributeSchema, SCHEMA};
+use crate::block_summary::FacetSummary;
+use crate::car_types::CAR_TYPES;
+use crate::config::CONFIG;
+use crate::currency::{ExchangeRates, PriceConverter};
+use crate::db_models::Offer;
+use crate::expiry::{now_millis, EvictionCounts, EvictionReason, ExpiryPolicy};
+use crate::facets::FacetSelection;
+use crate::geo::GeoFilter;
+use crate::index_tree::{IndexTree, IndexTreeOffer, ROOT_REGION};
 use crate::json_models::{
-    CarTypeCount, FreeKilometerRange, GetReponseBodyModel, PriceRange, RequestOffer, ResponseOffer,
-    SeatCount, SortOrder, VollKaskoCount, CarType
+    CountResponseModel, FreeKilometerRange, GetReponseBodyModel, PriceRange, RegionCount,
+    RequestOffer, ResponseOffer, SeatCount, SortOrder, StatsResponseModel, VollKaskoCount,
 };
+use crate::metrics::METRICS;
+use crate::query_cache::QueryCache;
+use crate::range_facets::RangeFacet;
+use crate::secondary_index::SecondaryIndexes;
+use crate::summary_stats::StatsRequest;
 use crate::GenericError;
+use bytes::Bytes;
 use fxhash::{FxBuildHasher, FxHashMap};
 use gxhash::HashMapExt;
-use itertools::Itertools;
-use std::collections::{BinaryHeap, HashMap};
-use tokio::sync::RwLock;
-
+use rayon::prelude::*;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::{Mutex, RwLock, RwLockWriteGuard};
 
 pub struct DBManager {
     pub index_tree_lock: RwLock<IndexTree>,
     pub dense_store_lock: RwLock<DenseStore>,
+    pub rates_lock: RwLock<ExchangeRates>,
+    pub schema: AttributeSchema,
+    query_cache: QueryCache,
+    /// Bumped by [`DBManager::set_rates`], so searches priced with earlier
+    /// rates are never cached under the current ones.
+    rates_generation: AtomicU64,
+    /// Searches expected to walk at least this many index entries are
+    /// scanned in parallel, one region per task.
+    parallel_threshold: usize,
+    /// Serializes compactions, which build their result outside the store locks.
+    compaction_lock: Mutex<()>,
 }
 
 use std::cmp::Ordering;
 
+#[derive(Clone)]
 struct HeapItem<'a> {
     sort_key: u32,
     offer: &'a Offer,
@@ -35,101 +63,163 @@ impl<'a> Eq for HeapItem<'a> {}
 
 impl<'a> PartialOrd for HeapItem<'a> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))    // Delegate to `Ord` implementation
+        Some(self.cmp(other)) // Delegate to `Ord` implementation
     }
 }
 
 impl<'a> Ord for HeapItem<'a> {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.sort_key.cmp(&other.sort_key)
+        self.sort_key
+            .cmp(&other.sort_key)
             .then_with(|| self.offer.id.cmp(&other.offer.id)) // Tie-breaker
     }
 }
 
+/// Running results of a search over some of its candidates. Tallies of
+/// disjoint candidate sets are combined with [`SearchScan::merge`].
+struct SearchTally<'a> {
+    page_offers_heap: BinaryHeap<HeapItem<'a>>,
+    total: u32,
+    vollkasko_count: VollKaskoCount,
+    car_type_count: Vec<u32>,
+    free_kilometers_facet: RangeFacet,
+    price_facet: RangeFacet,
+    stats: StatsRequest,
+    seats_count_map: FxHashMap<u32, u32>,
+    region_count: Vec<u32>,
+    attribute_counts: Vec<RangeFacet>,
+}
 
+/// What every scan of a search's candidates shares.
+struct SearchScan<'a> {
+    request_offer: &'a RequestOffer,
+    attribute_query: &'a AttributeQuery,
+    facets: FacetSelection,
+    region_groups: Option<Vec<u8>>,
+    rates: &'a ExchangeRates,
+    /// The requested statistics, before anything was added to them.
+    stats: StatsRequest,
+    collect_page: bool,
+    /// Offers up to the end of the requested page are kept.
+    page_end: usize,
+}
 
-impl DBManager {
-    pub fn new() -> Self {
-        Self {
-            index_tree_lock: IndexTree::populate_with_regions(&ROOT_REGION).into(),
-            dense_store_lock: DenseStore::new().into(),
-        }
+impl<'a> SearchScan<'a> {
+    /// An empty tally for the selected facets. Fails on invalid range facet
+    /// buckets.
+    fn tally(&self) -> Result<SearchTally<'a>, GenericError> {
+        let request_offer = self.request_offer;
+        Ok(SearchTally {
+            page_offers_heap: BinaryHeap::new(),
+            total: 0,
+            vollkasko_count: VollKaskoCount {
+                true_count: 0,
+                false_count: 0,
+            },
+            car_type_count: vec![0; CAR_TYPES.len()],
+            free_kilometers_facet: if self.facets.free_kilometers {
+                RangeFacet::new(
+                    request_offer.min_free_kilometer_width,
+                    request_offer.free_kilometer_buckets.as_deref(),
+                    CONFIG.max_facet_buckets,
+                )?
+            } else {
+                RangeFacet::unused()
+            },
+            price_facet: if self.facets.price_ranges {
+                RangeFacet::new(
+                    request_offer.price_range_width,
+                    request_offer.price_buckets.as_deref(),
+                    CONFIG.max_facet_buckets,
+                )?
+            } else {
+                RangeFacet::unused()
+            },
+            stats: self.stats.clone(),
+            seats_count_map: FxHashMap::new(),
+            region_count: vec![0; u8::MAX as usize + 1],
+            attribute_counts: self.attribute_query.counters(),
+        })
     }
 
-    pub async fn query_for(
-        &self,
-        request_offer: RequestOffer,
-    ) -> Result<GetReponseBodyModel, GenericError> {
-        let dense_store = self.dense_store_lock.read().await;
-        let index_tree = self.index_tree_lock.read().await;
-
-        let mut page_offers_heap = BinaryHeap::new();
-        let page_size = request_offer.page_size as usize;
-        let page_start = (request_offer.page * request_offer.page_size) as usize;
-        let page_end = page_start + page_size;
-
-        let offers_iter = index_tree
-            .get_available_offers(
-                request_offer.region_id,
-                request_offer.number_days,
-                request_offer.time_range_start,
-                request_offer.time_range_end,
-            )
-            .map(|offer_idx| &dense_store.all[offer_idx as usize]);
-
-        let mut vollkasko_count = VollKaskoCount {
-            true_count: 0,
-            false_count: 0,
-        };
+    fn scan(&self, tally: &mut SearchTally<'a>, offers: impl Iterator<Item = &'a Offer>) {
+        let mut converter = self
+            .request_offer
+            .currency
+            .map(|currency| PriceConverter::new(self.rates, currency));
 
-        let mut car_type_count = CarTypeCount {
-            small: 0,
-            sports: 0,
-            luxury: 0,
-            family: 0,
-        };
-
-        let mut free_kilometers_interval_mapping = FxHashMap::new();
-        let mut price_range_interval_mapping = FxHashMap::new();
-        let mut seats_count_map = FxHashMap::new();
-
-        for offer in offers_iter {
+        for offer in offers {
+            // Offers in a currency without a rate cannot be priced and are skipped.
+            let price = match &mut converter {
+                Some(converter) => match converter.convert(offer.price, offer.currency) {
+                    Some(price) => price,
+                    None => continue,
+                },
+                None => offer.price,
+            };
             let mut seats_incl = true;
             let mut car_type_incl = true;
             let mut only_vollkasko_ignored = true;
             let mut free_kilometers_incl = true;
             let mut price_range_incl = true;
 
-            if let Some(minNumberOfSeats) = request_offer.min_number_seats {
-                if offer.number_seats < minNumberOfSeats {
+            if let Some(min_number_seats) = self.request_offer.min_number_seats {
+                if offer.number_seats < min_number_seats {
                     seats_incl = false;
                 }
             }
-            if let Some(carType) = request_offer.car_type {
-                if ! (offer.car_type == carType) {
+            if let Some(car_type) = self.request_offer.car_type {
+                if !(offer.car_type == car_type) {
                     car_type_incl = false
                 }
             }
-            if let Some(vollkasko_required) = request_offer.only_vollkasko {
+            if let Some(vollkasko_required) = self.request_offer.only_vollkasko {
                 if vollkasko_required && !offer.has_vollkasko {
                     only_vollkasko_ignored = false;
                 }
             }
-            if let Some(minFreeKilometers) = request_offer.min_free_kilometer {
-                if offer.free_kilometers < minFreeKilometers {
+            if let Some(min_free_kilometers) = self.request_offer.min_free_kilometer {
+                if offer.free_kilometers < min_free_kilometers {
                     free_kilometers_incl = false;
                 }
             }
-            if let Some(maxPrice) = request_offer.max_price {
-                if maxPrice <= offer.price {
+            if let Some(max_price) = self.request_offer.max_price {
+                if max_price <= price {
                     price_range_incl = false;
                 }
             }
-            if let Some(minPrice) = request_offer.min_price {
-                if minPrice > offer.price {
+            if let Some(min_price) = self.request_offer.min_price {
+                if min_price > price {
                     price_range_incl = false;
                 }
             }
+            let mut failed_attribute = 0;
+            let mut attribute_failures = 0;
+            for filter in &self.attribute_query.filters {
+                if !filter.matches(&offer.attributes) {
+                    attribute_failures += 1;
+                    failed_attribute = filter.slot;
+                }
+            }
+            if attribute_failures > 0 {
+                // Like the built-in facets, an attribute's facet ignores its own filter.
+                if self.facets.attributes
+                    && attribute_failures == 1
+                    && seats_incl
+                    && car_type_incl
+                    && only_vollkasko_ignored
+                    && free_kilometers_incl
+                    && price_range_incl
+                {
+                    self.attribute_query.count(
+                        &mut tally.attribute_counts,
+                        failed_attribute,
+                        &offer.attributes,
+                    );
+                }
+                continue;
+            }
+
             match (
                 seats_incl,
                 car_type_incl,
@@ -138,109 +228,432 @@ impl DBManager {
                 price_range_incl,
             ) {
                 (true, true, true, true, true) => {
+                    tally.total += 1;
+                    if self.collect_page {
+                        let sort_key = match self.request_offer.sort_order {
+                            SortOrder::PriceAsc => price,
+                            SortOrder::PriceDesc => u32::MAX - price,
+                        };
 
-                    let sort_key = match request_offer.sort_order {
-                        SortOrder::PriceAsc => offer.price,
-                        SortOrder::PriceDesc => u32::MAX - offer.price,
-                    };
-
-                    let heap_item = HeapItem {
-                        sort_key,
-                        offer,
-                    };
-
-                    if page_offers_heap.len() < page_end {
-                        page_offers_heap.push(heap_item);
-                    } else if let Some(top_item) = page_offers_heap.peek() {
-                        if heap_item < *top_item {
-                            page_offers_heap.pop();
-                            page_offers_heap.push(heap_item);
-                        }
+                        self.push_page_offer(
+                            &mut tally.page_offers_heap,
+                            HeapItem { sort_key, offer },
+                        );
+                    }
+                    tally.stats.add_free_kilometers(offer.free_kilometers);
+                    tally.stats.add_price(price);
+                    if self.facets.is_none() {
+                        continue;
+                    }
+                    if self.facets.vollkasko {
+                        DBManager::handle_vollkasko_count(&mut tally.vollkasko_count, offer);
+                    }
+                    if self.facets.car_types {
+                        DBManager::handle_car_type_count(&mut tally.car_type_count, offer);
+                    }
+                    if self.facets.free_kilometers {
+                        tally.free_kilometers_facet.count(offer.free_kilometers);
+                    }
+                    if self.facets.price_ranges {
+                        tally.price_facet.count(price);
+                    }
+                    if self.facets.seats {
+                        DBManager::handle_seats_count(&mut tally.seats_count_map, offer);
+                    }
+                    if self.facets.attributes {
+                        self.attribute_query
+                            .count_all(&mut tally.attribute_counts, &offer.attributes);
+                    }
+                    if let Some(region_groups) = &self.region_groups {
+                        let group = region_groups[offer.most_specific_region_id as usize];
+                        tally.region_count[group as usize] += 1;
                     }
-                    Self::handle_vollkasko_count(&mut vollkasko_count, offer);
-                    Self::handle_car_type_count(&mut car_type_count, offer);
-                    Self::handle_free_kilometers_range(
-                        &request_offer,
-                        &mut free_kilometers_interval_mapping,
-                        offer,
-                    );
-                    Self::handle_price_range(
-                        &request_offer,
-                        &mut price_range_interval_mapping,
-                        offer,
-                    );
-                    Self::handle_seats_count(&mut seats_count_map, offer);
                 }
                 (true, true, true, true, false) => {
-                    Self::handle_price_range(
-                        &request_offer,
-                        &mut price_range_interval_mapping,
-                        offer,
-                    );
+                    if self.facets.price_ranges {
+                        tally.price_facet.count(price);
+                    }
+                    tally.stats.add_price(price);
                 }
                 (true, true, true, false, true) => {
-                    Self::handle_free_kilometers_range(
-                        &request_offer,
-                        &mut free_kilometers_interval_mapping,
-                        offer,
-                    );
+                    if self.facets.free_kilometers {
+                        tally.free_kilometers_facet.count(offer.free_kilometers);
+                    }
+                    tally.stats.add_free_kilometers(offer.free_kilometers);
                 }
-                (true, true, false, true, true) => {
-                    Self::handle_vollkasko_count(&mut vollkasko_count, offer);
+                (true, true, false, true, true) if self.facets.vollkasko => {
+                    DBManager::handle_vollkasko_count(&mut tally.vollkasko_count, offer);
                 }
-                (true, false, true, true, true) => {
-                    Self::handle_car_type_count(&mut car_type_count, offer);
+                (true, false, true, true, true) if self.facets.car_types => {
+                    DBManager::handle_car_type_count(&mut tally.car_type_count, offer);
                 }
-                (false, true, true, true, true) => {
-                    Self::handle_seats_count(&mut seats_count_map, offer);
+                (false, true, true, true, true) if self.facets.seats => {
+                    DBManager::handle_seats_count(&mut tally.seats_count_map, offer);
                 }
                 _ => {}
             }
         }
+    }
 
-        let mut price_ranges = Vec::with_capacity(price_range_interval_mapping.len());
+    fn merge(&self, mut tally: SearchTally<'a>, other: SearchTally<'a>) -> SearchTally<'a> {
+        for heap_item in other.page_offers_heap {
+            self.push_page_offer(&mut tally.page_offers_heap, heap_item);
+        }
+        tally.total += other.total;
+        tally.vollkasko_count.true_count += other.vollkasko_count.true_count;
+        tally.vollkasko_count.false_count += other.vollkasko_count.false_count;
+        for (counts, other) in [
+            (&mut tally.car_type_count, other.car_type_count),
+            (&mut tally.region_count, other.region_count),
+        ] {
+            for (count, other) in counts.iter_mut().zip(other) {
+                *count += other;
+            }
+        }
+        tally
+            .free_kilometers_facet
+            .merge(other.free_kilometers_facet);
+        tally.price_facet.merge(other.price_facet);
+        tally.stats.merge(other.stats);
+        for (number_seats, count) in other.seats_count_map {
+            *tally.seats_count_map.entry(number_seats).or_insert(0) += count;
+        }
+        for (counts, other) in tally
+            .attribute_counts
+            .iter_mut()
+            .zip(other.attribute_counts)
+        {
+            counts.merge(other);
+        }
+        tally
+    }
 
-        for key in price_range_interval_mapping.keys().sorted() {
-            let count = price_range_interval_mapping[key];
-            price_ranges.push(PriceRange {
-                start: *key,
-                end: *key + request_offer.price_range_width,
-                count,
-            });
+    #[inline(always)]
+    fn push_page_offer(&self, heap: &mut BinaryHeap<HeapItem<'a>>, heap_item: HeapItem<'a>) {
+        if heap.len() < self.page_end {
+            heap.push(heap_item);
+        } else if let Some(top_item) = heap.peek() {
+            if heap_item < *top_item {
+                heap.pop();
+                heap.push(heap_item);
+            }
         }
+    }
+}
+
+impl Default for DBManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        let mut kilometer_ranges = Vec::with_capacity(free_kilometers_interval_mapping.len());
-        for key in free_kilometers_interval_mapping.keys().sorted() {
-            let count = free_kilometers_interval_mapping[key];
-            kilometer_ranges.push(FreeKilometerRange {
-                start: *key,
-                end: *key + request_offer.min_free_kilometer_width,
-                count,
-            });
+impl DBManager {
+    pub fn new() -> Self {
+        Self {
+            index_tree_lock: IndexTree::populate_with_regions(&ROOT_REGION).into(),
+            dense_store_lock: DenseStore::new().into(),
+            rates_lock: RwLock::default(),
+            schema: SCHEMA.clone(),
+            query_cache: QueryCache::new(CONFIG.query_cache_entries),
+            rates_generation: AtomicU64::new(0),
+            parallel_threshold: default_parallel_threshold(),
+            compaction_lock: Mutex::new(()),
         }
+    }
 
-        // Extract the offers for the current page from the heap
-        let page_offers_vec: Vec<_> = page_offers_heap
-            .into_sorted_vec()
+    pub fn from_parts(index_tree: IndexTree, dense_store: DenseStore) -> Self {
+        Self {
+            index_tree_lock: index_tree.into(),
+            dense_store_lock: dense_store.into(),
+            rates_lock: RwLock::default(),
+            schema: SCHEMA.clone(),
+            query_cache: QueryCache::new(CONFIG.query_cache_entries),
+            rates_generation: AtomicU64::new(0),
+            parallel_threshold: default_parallel_threshold(),
+            compaction_lock: Mutex::new(()),
+        }
+    }
+
+    /// Sets the number of index entries from which searches run in parallel.
+    pub fn with_parallel_threshold(mut self, parallel_threshold: usize) -> Self {
+        self.parallel_threshold = parallel_threshold;
+        self
+    }
+
+    /// Replaces the attribute schema searches are evaluated against.
+    pub fn with_schema(mut self, schema: AttributeSchema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    pub async fn query_for(
+        &self,
+        request_offer: RequestOffer,
+    ) -> Result<GetReponseBodyModel, GenericError> {
+        self.search(request_offer, true).await
+    }
+
+    /// Evaluates a search and serializes the response, answering repeated
+    /// searches from the query cache while none of their regions changed.
+    pub async fn query_json(&self, mut request_offer: RequestOffer) -> Result<Bytes, GenericError> {
+        if !self.query_cache.is_enabled() {
+            return Ok(sonic_rs::to_vec(&self.query_for(request_offer).await?)?.into());
+        }
+        let key = QueryCache::key(
+            &mut request_offer,
+            self.rates_generation.load(AtomicOrdering::Acquire),
+        );
+        // Read before searching, so a write racing the search leaves the
+        // entry outdated rather than wrongly current.
+        let (versions, valid_until) = {
+            let dense_store = self.dense_store_lock.read().await;
+            let index_tree = self.index_tree_lock.read().await;
+            let versions: Vec<(u8, u64)> = request_offer
+                .region_ids
+                .iter()
+                .filter(|&&region_id| index_tree.contains_region(region_id))
+                .map(|&region_id| (region_id, index_tree.subtree_version(region_id)))
+                .collect();
+            (versions, dense_store.next_hold_lapse(now_millis()))
+        };
+        if let Some(json) = self.query_cache.get(&key, &versions, now_millis()) {
+            METRICS
+                .query_cache_hits
+                .fetch_add(1, AtomicOrdering::Relaxed);
+            return Ok(json);
+        }
+        METRICS
+            .query_cache_misses
+            .fetch_add(1, AtomicOrdering::Relaxed);
+        let json: Bytes = sonic_rs::to_vec(&self.query_for(request_offer).await?)?.into();
+        self.query_cache
+            .put(key, versions, valid_until, json.clone());
+        Ok(json)
+    }
+
+    /// Replaces the exchange rates, dropping cached searches priced with the
+    /// old ones.
+    pub async fn set_rates(&self, rates: ExchangeRates) {
+        let mut current = self.rates_lock.write().await;
+        *current = rates;
+        self.rates_generation.fetch_add(1, AtomicOrdering::AcqRel);
+        drop(current);
+        self.query_cache.clear();
+    }
+
+    /// Counts the offers matching a search without collecting a page of
+    /// results. Only facets selected with `facets=` are computed.
+    pub async fn count_for(
+        &self,
+        mut request_offer: RequestOffer,
+    ) -> Result<CountResponseModel, GenericError> {
+        request_offer
+            .facets
+            .get_or_insert_with(|| "none".to_string());
+        let facets = FacetSelection::parse(request_offer.facets.as_deref())?;
+        let response = self.search(request_offer, false).await?;
+        Ok(CountResponseModel {
+            count: response.total_count,
+            price_ranges: response.price_ranges,
+            car_type_counts: response.car_type_counts,
+            seats_count: response.seats_count,
+            free_kilometer_range: response.free_kilometer_range,
+            vollkasko_count: facets.vollkasko.then_some(response.vollkasko_count),
+            attribute_counts: response.attribute_counts,
+            region_counts: response.region_counts,
+            stats: response.stats,
+        })
+    }
+
+    /// Evaluates a search. Without `collect_page`, the response has no offers.
+    async fn search(
+        &self,
+        request_offer: RequestOffer,
+        collect_page: bool,
+    ) -> Result<GetReponseBodyModel, GenericError> {
+        let dense_store = self.dense_store_lock.read().await;
+        let index_tree = self.index_tree_lock.read().await;
+        let rates = self.rates_lock.read().await;
+        let attribute_query = self
+            .schema
+            .compile_query(&request_offer.attribute_filters)?;
+        let now = now_millis();
+
+        let geo_filter = GeoFilter::parse(request_offer.near.as_deref(), request_offer.radius_km)?;
+        let mut regions = index_tree.resolve_regions(
+            &request_offer.region_ids,
+            &request_offer.excluded_region_ids,
+        );
+        if let Some(geo_filter) = &geo_filter {
+            regions.retain(|&region_id| index_tree.region_within(region_id, geo_filter));
+        }
+        let stats = StatsRequest::parse(request_offer.stats.as_deref())?;
+        let facets = FacetSelection::parse(request_offer.facets.as_deref())?;
+        // Held offers are counted by the block summaries, so they are only
+        // used while nothing is held.
+        let unfiltered =
+            Self::is_unfiltered(&request_offer) && dense_store.next_hold_lapse(now) == u64::MAX;
+        if unfiltered
+            && !(collect_page && request_offer.page_size > 0)
+            && Self::is_summarizable(&stats, facets)
+        {
+            return Ok(Self::summarize(
+                &dense_store,
+                &index_tree,
+                &regions,
+                &request_offer,
+                facets,
+            ));
+        }
+        // Otherwise the summaries still provide the facets they count, and
+        // the scan below only collects the page and the other facets.
+        let summary = (unfiltered && (facets.car_types || facets.vollkasko || facets.seats))
+            .then(|| Self::summarize_facets(&dense_store, &index_tree, &regions, &request_offer));
+        let scan_facets = match summary {
+            Some(_) => FacetSelection {
+                car_types: false,
+                vollkasko: false,
+                seats: false,
+                ..facets
+            },
+            None => facets,
+        };
+        let candidates = dense_store.secondary_indexes().candidates(
+            &request_offer,
+            facets,
+            dense_store.live_count(),
+        );
+
+        let page_size = request_offer.page_size as usize;
+        let page_start = (request_offer.page * request_offer.page_size) as usize;
+
+        let offers_in = |regions: Vec<u8>| {
+            index_tree
+                .get_available_offers_in(
+                    regions,
+                    request_offer.number_days,
+                    request_offer.time_range_start,
+                    request_offer.time_range_end,
+                )
+                .filter(|&offer_idx| {
+                    candidates
+                        .as_ref()
+                        .is_none_or(|candidates| candidates.contains(offer_idx))
+                })
+                .filter(|&offer_idx| !dense_store.is_held(offer_idx, now))
+                .map(|offer_idx| &dense_store.all[offer_idx as usize])
+        };
+
+        let scan = SearchScan {
+            request_offer: &request_offer,
+            attribute_query: &attribute_query,
+            facets: scan_facets,
+            region_groups: facets
+                .regions
+                .then(|| index_tree.region_groups(&request_offer.region_ids)),
+            rates: &rates,
+            stats,
+            collect_page,
+            page_end: page_start + page_size,
+        };
+        // Large searches scan each region on its own rayon task and merge
+        // the results; small ones stay on the current thread.
+        let tally = if regions.len() > 1
+            && index_tree.candidate_estimate(&regions, request_offer.number_days)
+                >= self.parallel_threshold
+        {
+            // The worker is blocked until the rayon tasks are done; let the
+            // runtime move its other tasks elsewhere meanwhile.
+            block_in_place(|| {
+                regions
+                    .par_iter()
+                    .map(|&region_id| -> Result<_, GenericError> {
+                        let mut tally = scan.tally()?;
+                        scan.scan(&mut tally, offers_in(vec![region_id]));
+                        Ok(tally)
+                    })
+                    .try_reduce_with(|tally, other| Ok(scan.merge(tally, other)))
+            })
+            .unwrap_or_else(|| scan.tally())?
+        } else {
+            let mut tally = scan.tally()?;
+            scan.scan(&mut tally, offers_in(regions));
+            tally
+        };
+        let SearchTally {
+            page_offers_heap,
+            total,
+            mut vollkasko_count,
+            mut car_type_count,
+            free_kilometers_facet,
+            price_facet,
+            stats,
+            mut seats_count_map,
+            region_count,
+            attribute_counts,
+        } = tally;
+        if let Some(summary) = summary {
+            if facets.vollkasko {
+                vollkasko_count = VollKaskoCount {
+                    true_count: summary.vollkasko,
+                    false_count: summary.len - summary.vollkasko,
+                };
+            }
+            if facets.car_types {
+                car_type_count = summary.car_types;
+            }
+            if facets.seats {
+                seats_count_map = summary.seats.into_iter().collect();
+            }
+        }
+
+        let price_ranges = price_facet
+            .into_ranges(CONFIG.max_facet_buckets)
             .into_iter()
+            .map(|(start, end, count)| PriceRange { start, end, count })
+            .collect();
+        let kilometer_ranges = free_kilometers_facet
+            .into_ranges(CONFIG.max_facet_buckets)
+            .into_iter()
+            .map(|(start, end, count)| FreeKilometerRange { start, end, count })
             .collect();
 
+        // Extract the offers for the current page from the heap
+        let page_offers_vec: Vec<_> = page_offers_heap.into_sorted_vec().into_iter().collect();
 
         // Paginate
         let paged_offers = page_offers_vec
             .into_iter()
             .skip(page_start)
             .take(page_size)
-            .map(|item| ResponseOffer {
-                ID: item.offer.id.clone(),
-                data: item.offer.data.clone(),
+            .map(|item| {
+                let region_id = item.offer.most_specific_region_id;
+                let include_region = request_offer.include_region;
+                ResponseOffer {
+                    ID: item.offer.id.clone(),
+                    data: item.offer.data.clone(),
+                    most_specific_region_id: include_region.then_some(region_id),
+                    region_name: include_region
+                        .then(|| index_tree.region_name(region_id as u8))
+                        .flatten()
+                        .map(str::to_string),
+                }
             })
             .collect();
 
         Ok(GetReponseBodyModel {
             offers: paged_offers,
+            total_count: total,
+            total_pages: match request_offer.page_size {
+                0 => 0,
+                page_size => total.div_ceil(page_size),
+            },
             price_ranges,
-            car_type_counts: car_type_count,
+            car_type_counts: if facets.car_types {
+                CAR_TYPES.facet(&car_type_count)
+            } else {
+                BTreeMap::new()
+            },
             seats_count: seats_count_map
                 .into_iter()
                 .map(|(number_seats, count)| SeatCount {
@@ -250,52 +663,150 @@ impl DBManager {
                 .collect(),
             free_kilometer_range: kilometer_ranges,
             vollkasko_count,
+            attribute_counts: if facets.attributes {
+                attribute_query.facets(&self.schema, attribute_counts)
+            } else {
+                Vec::new()
+            },
+            region_counts: region_count[..u8::MAX as usize]
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(region_id, &count)| RegionCount {
+                    region_id: region_id as u8,
+                    count,
+                })
+                .collect(),
+            stats: stats.finish(),
         })
     }
 
-    #[inline(always)]
-    fn handle_seats_count(seats_count_map: &mut HashMap<u32, u32, FxBuildHasher>, offer: &Offer) {
-        seats_count_map
-            .entry(offer.number_seats)
-            .and_modify(|count| *count += 1)
-            .or_insert(1);
+    /// Whether a search has no optional filters, so the index block summaries
+    /// count the same offers as a scan.
+    fn is_unfiltered(request_offer: &RequestOffer) -> bool {
+        request_offer.min_number_seats.is_none()
+            && request_offer.car_type.is_none()
+            && request_offer.only_vollkasko != Some(true)
+            && request_offer.min_free_kilometer.is_none()
+            && request_offer.min_price.is_none()
+            && request_offer.max_price.is_none()
+            && request_offer.currency.is_none()
+            && request_offer.attribute_filters.is_empty()
     }
 
-    #[inline(always)]
-    fn handle_price_range(
+    /// Whether an unfiltered search without a page can be answered from the
+    /// index block summaries alone: its facets are among those the blocks
+    /// count.
+    fn is_summarizable(stats: &StatsRequest, facets: FacetSelection) -> bool {
+        stats.is_empty()
+            && !facets.price_ranges
+            && !facets.free_kilometers
+            && !facets.attributes
+            && !facets.regions
+    }
+
+    /// Answers a search that [`Self::is_summarizable`] from the block
+    /// summaries.
+    fn summarize(
+        dense_store: &DenseStore,
+        index_tree: &IndexTree,
+        regions: &[u8],
         request_offer: &RequestOffer,
-        price_range_interval_mapping: &mut HashMap<u32, u32, FxBuildHasher>,
-        offer: &Offer,
-    ) {
-        let lower_bound =
-            (offer.price / request_offer.price_range_width) * request_offer.price_range_width;
-        price_range_interval_mapping
-            .entry(lower_bound)
-            .and_modify(|count| *count += 1)
-            .or_insert(1);
+        facets: FacetSelection,
+    ) -> GetReponseBodyModel {
+        let summary = Self::summarize_facets(dense_store, index_tree, regions, request_offer);
+        let total = summary.len;
+        GetReponseBodyModel {
+            offers: Vec::new(),
+            total_count: total,
+            total_pages: match request_offer.page_size {
+                0 => 0,
+                page_size => total.div_ceil(page_size),
+            },
+            price_ranges: Vec::new(),
+            car_type_counts: if facets.car_types {
+                CAR_TYPES.facet(&summary.car_types)
+            } else {
+                BTreeMap::new()
+            },
+            seats_count: if facets.seats {
+                summary
+                    .seats
+                    .iter()
+                    .map(|&(number_seats, count)| SeatCount {
+                        number_seats,
+                        count,
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            free_kilometer_range: Vec::new(),
+            vollkasko_count: if facets.vollkasko {
+                VollKaskoCount {
+                    true_count: summary.vollkasko,
+                    false_count: total - summary.vollkasko,
+                }
+            } else {
+                VollKaskoCount {
+                    true_count: 0,
+                    false_count: 0,
+                }
+            },
+            attribute_counts: Vec::new(),
+            region_counts: Vec::new(),
+            stats: BTreeMap::new(),
+        }
     }
 
-    #[inline(always)]
-    fn handle_free_kilometers_range(
+    /// Car type, vollkasko and seat counts of an unfiltered search, from the
+    /// block summaries of the fixed offers, visiting only the offers at the
+    /// edges of the time range and the flexible ones.
+    fn summarize_facets(
+        dense_store: &DenseStore,
+        index_tree: &IndexTree,
+        regions: &[u8],
         request_offer: &RequestOffer,
-        free_kilometers_interval_mapping: &mut HashMap<u32, u32, FxBuildHasher>,
-        offer: &Offer,
-    ) {
-        let lower_bound = (offer.free_kilometers / request_offer.min_free_kilometer_width)
-            * request_offer.min_free_kilometer_width;
-        free_kilometers_interval_mapping
-            .entry(lower_bound)
+    ) -> FacetSummary {
+        METRICS
+            .summarized_searches
+            .fetch_add(1, AtomicOrdering::Relaxed);
+        let mut summary = FacetSummary::default();
+
+        let mut edges = FacetSummary::default();
+        index_tree.summarize_fixed_offers(
+            regions,
+            request_offer.number_days,
+            request_offer.time_range_start,
+            request_offer.time_range_end,
+            &mut summary,
+            |idx| edges.add_offer(&dense_store.all[idx as usize]),
+        );
+        for idx in index_tree.available_windows(
+            regions.to_vec(),
+            request_offer.number_days,
+            request_offer.time_range_start,
+            request_offer.time_range_end,
+        ) {
+            edges.add_offer(&dense_store.all[idx as usize]);
+        }
+        summary.merge(&edges);
+
+        summary
+    }
+
+    #[inline(always)]
+    fn handle_seats_count(seats_count_map: &mut HashMap<u32, u32, FxBuildHasher>, offer: &Offer) {
+        seats_count_map
+            .entry(offer.number_seats)
             .and_modify(|count| *count += 1)
             .or_insert(1);
     }
 
     #[inline(always)]
-    fn handle_car_type_count(car_type_count: &mut CarTypeCount, offer: &Offer) {
-        match offer.car_type {
-            CarType::Small => car_type_count.small += 1,
-            CarType::Sports => car_type_count.sports += 1,
-            CarType::Luxury => car_type_count.luxury += 1,
-            CarType::Family => car_type_count.family += 1,
+    fn handle_car_type_count(car_type_count: &mut [u32], offer: &Offer) {
+        if let Some(count) = car_type_count.get_mut(offer.car_type.id() as usize) {
+            *count += 1;
         }
     }
 
@@ -308,218 +819,360 @@ impl DBManager {
         }
     }
 
-    fn get_car_type_count(
-        offers: &[Offer],
-        excluded_offers: &[&Offer],
-        request_offer: &RequestOffer,
-    ) -> CarTypeCount {
-        let filtered_offers_count = offers.len() as u32;
-        match request_offer.car_type {
-            None => {
-                let mut small = 0;
-                let mut sports = 0;
-                let mut luxury = 0;
-                let mut family = 0;
-                for offer in offers {
-                    match offer.car_type {
-                        CarType::Small => small += 1,
-                        CarType::Sports => sports += 1,
-                        CarType::Luxury => luxury += 1,
-                        CarType::Family => family += 1,
-                    }
-                }
-                CarTypeCount {
-                    small,
-                    sports,
-                    luxury,
-                    family,
-                }
-            }
-            Some(filtered_car_type) => {
-                let mut small_excluded = 0;
-                let mut family_excluded = 0;
-                let mut luxury_excluded = 0;
-                let mut sports_excluded = 0;
-                for offer in excluded_offers {
-                    match offer.car_type {
-                        CarType::Small => small_excluded += 1,
-                        CarType::Sports => sports_excluded += 1,
-                        CarType::Luxury => luxury_excluded += 1,
-                        CarType::Family => family_excluded += 1,
-                    };
-                }
-                CarTypeCount {
-                    small: if CarType::Small.eq(&filtered_car_type) {
-                        filtered_offers_count
-                    } else {
-                        small_excluded
-                    },
-                    sports: if CarType::Sports.eq(&filtered_car_type) {
-                        filtered_offers_count
-                    } else {
-                        sports_excluded
-                    },
-                    luxury: if CarType::Luxury.eq(&filtered_car_type) {
-                        filtered_offers_count
-                    } else {
-                        luxury_excluded
-                    },
-                    family: if CarType::Family.eq(&filtered_car_type) {
-                        filtered_offers_count
-                    } else {
-                        family_excluded
-                    },
-                }
+    /// Inserts a batch of validated offers into the dense store and the index
+    /// tree under a single write lock, assigning their `idx` in order.
+    /// Returns the number of offers that were accepted.
+    pub async fn insert_offers(&self, offers: Vec<Offer>) -> u32 {
+        let mut dense_store = self.dense_store_lock.write().await;
+        let mut index_tree = self.index_tree_lock.write().await;
+
+        let mut accepted = 0;
+        for mut offer in offers {
+            let region_id = offer.most_specific_region_id as u8;
+            if !index_tree.contains_region(region_id) {
+                continue;
             }
+            offer.idx = dense_store.all.len() as u32;
+            index_tree.insert_offer(region_id, &offer);
+            dense_store.insert(offer);
+            accepted += 1;
         }
+        accepted
     }
 
-    fn sort_orders_and_paginate(
-        offers: &mut Vec<&Offer>,
-        request_offer: RequestOffer,
-    ) -> Vec<ResponseOffer> {
-        if offers.is_empty() {
-            return vec![];
+    pub async fn memory_stats(&self) -> StatsResponseModel {
+        let dense_store = self.dense_store_lock.read().await;
+        let index_tree = self.index_tree_lock.read().await;
+        let store_bytes = dense_store.slot_bytes() as u64;
+        let string_bytes = dense_store.string_bytes() as u64;
+        let index_bytes = index_tree.memory_bytes() as u64;
+        let bitmap_bytes = dense_store.secondary_indexes().memory_bytes() as u64;
+        StatsResponseModel {
+            offers: (dense_store.all.len() - dense_store.removed_count()) as u64,
+            removed_offers: dense_store.removed_count() as u64,
+            store_bytes,
+            string_bytes,
+            index_bytes,
+            bitmap_bytes,
+            total_bytes: store_bytes + string_bytes + index_bytes + bitmap_bytes,
+            memory_limit_bytes: CONFIG.memory_limit_bytes.map(|limit| limit as u64),
         }
+    }
 
-        match request_offer.sort_order {
-            SortOrder::PriceAsc => offers.sort_by(|a, b| {
-                let comp = a.price.cmp(&b.price);
-                if comp.is_eq() {
-                    return a.id.cmp(&b.id);
-                }
-                return comp;
-            }),
-            SortOrder::PriceDesc => offers.sort_by(|a, b| {
-                let comp = b.price.cmp(&a.price);
-                if comp.is_eq() {
-                    return a.id.cmp(&b.id);
-                }
-                return comp;
-            }),
+    /// Whether inserting `offers` stays within `CONFIG.memory_limit_bytes`,
+    /// including the store growth the insert would trigger.
+    pub async fn has_capacity_for(&self, offers: &[Offer]) -> bool {
+        let Some(limit) = CONFIG.memory_limit_bytes else {
+            return true;
+        };
+        let (used, len, capacity) = {
+            let dense_store = self.dense_store_lock.read().await;
+            let index_tree = self.index_tree_lock.read().await;
+            let used = dense_store.slot_bytes()
+                + dense_store.string_bytes()
+                + index_tree.memory_bytes()
+                + dense_store.secondary_indexes().memory_bytes();
+            (used, dense_store.all.len(), dense_store.all.capacity())
+        };
+
+        let needed = len + offers.len();
+        let store_growth = if needed > capacity {
+            (needed.max(capacity * 2) - capacity) * std::mem::size_of::<Offer>()
+        } else {
+            0
+        };
+        let strings: usize = offers.iter().map(offer_string_bytes).sum();
+        let index = offers.len() * std::mem::size_of::<IndexTreeOffer>();
+        used + store_growth + strings + index <= limit
+    }
+
+    /// Evicts every offer the policy considers expired at `now` (Unix ms):
+    /// their index entries are dropped and their store slots are freed.
+    pub async fn evict(&self, policy: &ExpiryPolicy, now: u64) -> EvictionCounts {
+        // Scan under the read lock so searches keep running meanwhile.
+        let (candidates, epoch) = {
+            let dense_store = self.dense_store_lock.read().await;
+            let candidates: Vec<u32> = dense_store
+                .all
+                .iter()
+                .filter(|offer| !dense_store.is_removed(offer.idx))
+                .filter(|offer| {
+                    let latest_start = dense_store.latest_start(offer.idx);
+                    policy.eviction_reason(offer, latest_start, now).is_some()
+                })
+                .map(|offer| offer.idx)
+                .collect();
+            (candidates, dense_store.epoch())
+        };
+        let mut counts = EvictionCounts::default();
+        if candidates.is_empty() {
+            return counts;
         }
 
-        offers
+        let mut dense_store = self.dense_store_lock.write().await;
+        let mut index_tree = self.index_tree_lock.write().await;
+        if dense_store.epoch() != epoch {
+            // Compacted or cleared in between; the next pass picks them up.
+            return counts;
+        }
+        let evicted: Vec<u32> = candidates
             .into_iter()
-            .skip(((request_offer.page) * request_offer.page_size) as usize) // pagination starts at 0
-            .take(request_offer.page_size as usize)
-            .map(|o| ResponseOffer {
-                ID: o.id.clone(),
-                data: o.data.clone(),
+            .filter(|&idx| !dense_store.is_removed(idx))
+            .filter(|&idx| {
+                let offer = &dense_store.all[idx as usize];
+                match policy.eviction_reason(offer, dense_store.latest_start(idx), now) {
+                    Some(EvictionReason::Expired) => {
+                        counts.expired += 1;
+                        true
+                    }
+                    Some(EvictionReason::Started) => {
+                        counts.started += 1;
+                        true
+                    }
+                    None => false,
+                }
             })
-            .collect()
+            .collect();
+        index_tree.remove_offers(evicted.iter().map(|&idx| &dense_store.all[idx as usize]));
+        for idx in evicted {
+            dense_store.remove(idx);
+        }
+        counts
     }
 
-    fn to_free_kilometers_offers<'a>(
-        offers: impl Iterator<Item = &'a Offer>,
-        free_kilometer_width: u32,
-    ) -> Vec<FreeKilometerRange> {
-        let mut interval_mapping = FxHashMap::new();
+    /// Hides the offer with the given ID from searches for `ttl_secs`.
+    /// Returns the Unix ms at which the hold lapses.
+    pub async fn hold_offer(&self, id: &str, ttl_secs: u64, now: u64) -> Result<Hold, HoldError> {
+        let (mut dense_store, idx) = self.find_for_write(id).await;
+        let idx = idx.ok_or(HoldError::UnknownOffer)?;
+        let until = now.saturating_add(ttl_secs.saturating_mul(1000));
+        let hold = dense_store
+            .hold(idx, until, now)
+            .ok_or(HoldError::AlreadyHeld)?;
+        self.touch_offer_region(&dense_store, idx).await;
+        Ok(hold)
+    }
 
-        for offer in offers {
-            let lower_bound = (offer.free_kilometers / free_kilometer_width) * free_kilometer_width;
-            interval_mapping
-                .entry(lower_bound)
-                .and_modify(|count| *count += 1)
-                .or_insert(1);
-        }
+    /// Releases the hold on the offer with the given ID. Returns false if the
+    /// offer is unknown or not held.
+    pub async fn release_offer(&self, id: &str, now: u64) -> bool {
+        let (mut dense_store, idx) = self.find_for_write(id).await;
+        let Some(idx) = idx.filter(|&idx| dense_store.release(idx, now)) else {
+            return false;
+        };
+        self.touch_offer_region(&dense_store, idx).await;
+        true
+    }
 
-        let mut kilometer_ranges = Vec::with_capacity(interval_mapping.len());
-        for key in interval_mapping.keys().sorted() {
-            let count = interval_mapping[key];
-            kilometer_ranges.push(FreeKilometerRange {
-                start: *key,
-                end: *key + free_kilometer_width,
-                count,
-            });
-        }
-        kilometer_ranges
+    /// Invalidates cached searches that may include or exclude the offer
+    /// because of a hold. Takes the index lock after the caller's store lock.
+    async fn touch_offer_region(&self, dense_store: &DenseStore, idx: u32) {
+        let region_id = dense_store.all[idx as usize].most_specific_region_id;
+        self.index_tree_lock
+            .write()
+            .await
+            .touch_region(region_id as u8);
     }
 
-    fn to_vollkasko_offers<'a>(offers: impl Iterator<Item = &'a Offer>) -> VollKaskoCount {
-        // counts for vollkasko occurences
-        let (mut true_count, mut false_count) = (0, 0);
+    /// Looks an offer up and returns it with the store write-locked.
+    async fn find_for_write(&self, id: &str) -> (RwLockWriteGuard<'_, DenseStore>, Option<u32>) {
+        let dense_store = self.dense_store_lock.write().await;
+        let idx = dense_store.find(id);
+        (dense_store, idx)
+    }
 
-        for offer in offers {
-            if offer.has_vollkasko {
-                true_count += 1
-            } else {
-                false_count += 1
+    /// Books `start_date..end_date` of the offer with the given ID and returns
+    /// its remaining free intervals. Fixed offers can only be booked as a
+    /// whole; flexible ones are split around the booking. Offers without free
+    /// intervals left are removed. While an offer is held, only a booking
+    /// with the token of its hold goes through, and it ends the hold.
+    pub async fn book_offer(
+        &self,
+        id: &str,
+        start_date: u64,
+        end_date: u64,
+        hold_token: Option<u64>,
+        now: u64,
+    ) -> Result<Vec<(u64, u64)>, BookingError> {
+        let (mut dense_store, idx) = self.find_for_write(id).await;
+        let idx = idx.ok_or(BookingError::UnknownOffer)?;
+        if let Some(hold) = dense_store.active_hold(idx, now) {
+            if hold_token != Some(hold.token) {
+                return Err(BookingError::Unavailable);
             }
         }
+        let mut index_tree = self.index_tree_lock.write().await;
 
-        VollKaskoCount {
-            true_count,
-            false_count,
+        let offer = &dense_store.all[idx as usize];
+        let old_intervals = dense_store.free_intervals(idx);
+        let remaining = if offer.flexible {
+            let position = old_intervals
+                .iter()
+                .position(|&(start, end)| start <= start_date && end_date <= end)
+                .ok_or(BookingError::Unavailable)?;
+            let (start, end) = old_intervals[position];
+            let mut remaining = old_intervals.clone();
+            remaining.splice(
+                position..=position,
+                [(start, start_date), (end_date, end)]
+                    .into_iter()
+                    .filter(|(start, end)| start < end),
+            );
+            remaining
+        } else if (offer.start_date, offer.end_date) == (start_date, end_date) {
+            Vec::new()
+        } else {
+            return Err(BookingError::Unavailable);
+        };
+
+        if remaining.is_empty() {
+            index_tree.remove_offers([offer]);
+            dense_store.remove(idx);
+        } else {
+            index_tree.replace_free_intervals(offer, &old_intervals, &remaining);
+            dense_store.set_free_intervals(idx, remaining.clone());
+            // The checkout is over; the rest of the offer is free again.
+            dense_store.release(idx, now);
         }
+        Ok(remaining)
     }
 
-    fn to_car_type_count<'a>(offers: impl Iterator<Item = &'a Offer>) -> CarTypeCount {
-        // counts for car types
-        let (mut small, mut sports, mut luxury, mut family) = (0, 0, 0, 0);
+    pub async fn release_expired_holds(&self, now: u64) -> usize {
+        self.dense_store_lock
+            .write()
+            .await
+            .release_expired_holds(now)
+    }
 
-        for offer in offers {
-            match offer.car_type {
-                CarType::Small => small += 1,
-                CarType::Sports => sports += 1,
-                CarType::Luxury => luxury += 1,
-                CarType::Family => family += 1,
+    /// Reclaims the slots of removed offers and remaps the index tree.
+    /// Returns the number of reclaimed slots.
+    ///
+    /// The compacted store and index are built from a read-locked view, so
+    /// searches keep running. Writes that happened in the meantime are then
+    /// replayed onto the copies under the write locks, right before they are
+    /// swapped in.
+    pub async fn compact(&self) -> usize {
+        let _compaction = self.compaction_lock.lock().await;
+
+        let (mut new_store, mut new_tree, new_idx, base_len, base_removed, epoch) = {
+            let dense_store = self.dense_store_lock.read().await;
+            let index_tree = self.index_tree_lock.read().await;
+            if dense_store.removed_count() == 0 {
+                return 0;
             }
-        }
+            let new_idx = dense_store.compacted_positions();
+            let new_store = dense_store.compacted_copy(&new_idx);
+            let mut new_tree = index_tree.clone();
+            new_tree.remap_offers(&new_idx);
+            (
+                new_store,
+                new_tree,
+                new_idx,
+                dense_store.all.len(),
+                dense_store.removed_count(),
+                dense_store.epoch(),
+            )
+        };
 
-        CarTypeCount {
-            small,
-            sports,
-            luxury,
-            family,
-        }
-    }
+        let (old_store, old_tree, reclaimed);
+        {
+            let mut dense_store = self.dense_store_lock.write().await;
+            let mut index_tree = self.index_tree_lock.write().await;
+            if dense_store.epoch() != epoch {
+                return 0;
+            }
 
-    pub fn to_price_ranges_offers<'a>(
-        offers: impl Iterator<Item = &'a Offer>,
-        price_range_width: u32,
-    ) -> Vec<PriceRange> {
-        let mut interval_mapping = FxHashMap::new();
+            // Offers removed while the copies were built.
+            let removed_since: Vec<u32> = dense_store
+                .removed_after(base_removed)
+                .iter()
+                .filter(|&&idx| (idx as usize) < base_len)
+                .map(|&idx| new_idx[idx as usize])
+                .collect();
+            new_tree.remove_offers(
+                removed_since
+                    .iter()
+                    .map(|&idx| &new_store.all[idx as usize]),
+            );
+            for idx in removed_since {
+                new_store.remove(idx);
+            }
 
-        for offer in offers {
-            let lower_bound = (offer.price / price_range_width) * price_range_width;
-            interval_mapping
-                .entry(lower_bound)
-                .and_modify(|count| *count += 1)
-                .or_insert(1);
-        }
+            // Offers booked while the copies were built.
+            let booked_since: Vec<(u32, Vec<(u64, u64)>)> = dense_store
+                .free_intervals
+                .iter()
+                .filter(|(&idx, _)| (idx as usize) < base_len && !dense_store.is_removed(idx))
+                .map(|(&idx, intervals)| (new_idx[idx as usize], intervals))
+                .filter(|(idx, intervals)| new_store.free_intervals.get(idx) != Some(intervals))
+                .map(|(idx, intervals)| (idx, intervals.clone()))
+                .collect();
+            for (idx, intervals) in booked_since {
+                let old_intervals = new_store.free_intervals(idx);
+                new_tree.replace_free_intervals(
+                    &new_store.all[idx as usize],
+                    &old_intervals,
+                    &intervals,
+                );
+                new_store.set_free_intervals(idx, intervals);
+            }
 
-        let mut price_ranges = Vec::with_capacity(interval_mapping.len());
+            new_store.holds = dense_store
+                .holds
+                .iter()
+                .filter(|(&idx, _)| (idx as usize) < base_len && !dense_store.is_removed(idx))
+                .map(|(&idx, &hold)| (new_idx[idx as usize], hold))
+                .collect();
 
-        for key in interval_mapping.keys().sorted() {
-            let count = interval_mapping[key];
-            price_ranges.push(PriceRange {
-                start: *key,
-                end: *key + price_range_width,
-                count,
-            });
-        }
+            // Offers inserted while the copies were built.
+            for offer in &dense_store.all[base_len..] {
+                if dense_store.is_removed(offer.idx) {
+                    continue;
+                }
+                let old_idx = offer.idx;
+                let mut offer = offer.clone();
+                offer.idx = new_store.all.len() as u32;
+                new_tree.insert_offer(offer.most_specific_region_id as u8, &offer);
+                if let Some(&hold) = dense_store.holds.get(&old_idx) {
+                    new_store.holds.insert(offer.idx, hold);
+                }
+                if let Some(intervals) = dense_store.free_intervals.get(&old_idx) {
+                    new_tree.replace_free_intervals(
+                        &offer,
+                        &[(offer.start_date, offer.end_date)],
+                        intervals,
+                    );
+                    new_store.set_free_intervals(offer.idx, intervals.clone());
+                }
+                new_store.insert(offer);
+            }
 
-        price_ranges
+            new_tree.advance_versions_past(&index_tree);
+            reclaimed = dense_store.all.len() - new_store.all.len();
+            new_store.epoch = dense_store.epoch + 1;
+            old_store = std::mem::replace(&mut *dense_store, new_store);
+            old_tree = std::mem::replace(&mut *index_tree, new_tree);
+        }
+        // Freeing the old offers' strings can take a while; do it unlocked.
+        drop(old_store);
+        drop(old_tree);
+        reclaimed
     }
 
-    pub fn to_seat_number_offers<'a>(offers: impl Iterator<Item = &'a Offer>) -> Vec<SeatCount> {
-        let mut count_map = FxHashMap::new();
-
-        for offer in offers {
-            count_map
-                .entry(offer.number_seats)
-                .and_modify(|count| *count += 1)
-                .or_insert(1);
+    /// Compacts the store once removed offers make up at least
+    /// `CONFIG.compaction_threshold` of its slots.
+    pub async fn compact_if_fragmented(&self) -> usize {
+        let fragmented = {
+            let dense_store = self.dense_store_lock.read().await;
+            dense_store.removed_count() > 0
+                && dense_store.removed_count() as f64
+                    >= dense_store.all.len() as f64 * CONFIG.compaction_threshold
+        };
+        if fragmented {
+            self.compact().await
+        } else {
+            0
         }
-
-        count_map
-            .into_iter()
-            .map(|(number_seats, count)| SeatCount {
-                number_seats,
-                count,
-            })
-            .collect()
     }
 
     pub async fn cleanup(&self) -> Result<(), GenericError> {
@@ -529,24 +1182,991 @@ impl DBManager {
         }
         {
             let mut dense_store_lock = self.dense_store_lock.write().await;
-            dense_store_lock.all.clear();
+            dense_store_lock.clear();
         }
         Ok(())
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookingError {
+    UnknownOffer,
+    /// The requested range is not free, or does not match a fixed offer.
+    Unavailable,
+}
+
+/// A hold on an offer. Only a booking presenting its `token` may book the
+/// offer while it is held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hold {
+    /// Unix ms at which the hold lapses.
+    pub until: u64,
+    pub token: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoldError {
+    UnknownOffer,
+    AlreadyHeld,
+}
+
 pub struct DenseStore {
     pub all: Vec<Offer>,
+    /// Slots of offers that were removed and are waiting for compaction.
+    removed: Vec<bool>,
+    removed_count: usize,
+    /// Slots in the order their offers were removed since the store was built,
+    /// so a compaction can replay just the removals it raced with.
+    removal_log: Vec<u32>,
+    /// Heap memory of the live offers' `id` and `data` strings.
+    string_bytes: usize,
+    /// The `idx` of every live offer by ID. Of offers sharing an ID, the
+    /// last one inserted is found.
+    ids: FxHashMap<String, u32>,
+    /// Offers hidden from searches while someone checks them out.
+    holds: FxHashMap<u32, Hold>,
+    /// Seeds the hold tokens, so they cannot be guessed from one another.
+    hold_tokens: RandomState,
+    /// Remaining free intervals of flexible offers that have been booked.
+    /// Flexible offers without an entry are free over their whole window.
+    free_intervals: FxHashMap<u32, Vec<(u64, u64)>>,
+    /// Bumped whenever offers move to a different `idx` (compaction) or the
+    /// store is cleared, invalidating any `idx` taken before.
+    epoch: u64,
+    /// Bitmaps of the live offers per car type, vollkasko flag and seats.
+    secondary_indexes: SecondaryIndexes,
+}
+
+impl Default for DenseStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DenseStore {
     pub fn new() -> Self {
+        Self::from_offers(Vec::with_capacity(CONFIG.initial_store_capacity))
+    }
+
+    pub fn from_offers(all: Vec<Offer>) -> Self {
+        let string_bytes = all.par_iter().map(offer_string_bytes).sum();
+        let secondary_indexes = SecondaryIndexes::from_offers(&all);
+        let ids = all
+            .iter()
+            .map(|offer| (offer.id.clone(), offer.idx))
+            .collect();
         Self {
-            all: Vec::with_capacity(1 << 25),
+            ids,
+            secondary_indexes,
+            all,
+            removed: Vec::new(),
+            removed_count: 0,
+            removal_log: Vec::new(),
+            string_bytes,
+            holds: FxHashMap::default(),
+            hold_tokens: RandomState::new(),
+            free_intervals: FxHashMap::default(),
+            epoch: 0,
         }
     }
 
+    /// Heap memory of the offer slots and removal markers, excluding strings.
+    pub fn slot_bytes(&self) -> usize {
+        self.all.capacity() * std::mem::size_of::<Offer>()
+            + self.removed.capacity()
+            + self.removal_log.capacity() * std::mem::size_of::<u32>()
+    }
+
+    pub fn string_bytes(&self) -> usize {
+        self.string_bytes
+    }
+
+    pub fn secondary_indexes(&self) -> &SecondaryIndexes {
+        &self.secondary_indexes
+    }
+
+    pub fn live_count(&self) -> usize {
+        self.all.len() - self.removed_count
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn is_removed(&self, idx: u32) -> bool {
+        self.removed.get(idx as usize).copied().unwrap_or(false)
+    }
+
+    /// The slots removed after the first `since` removals, in removal order.
+    pub fn removed_after(&self, since: usize) -> &[u32] {
+        &self.removal_log[since..]
+    }
+
+    /// Number of slots held by removed offers.
+    pub fn removed_count(&self) -> usize {
+        self.removed_count
+    }
+
+    /// Marks an offer as removed and releases its strings. The slot itself is
+    /// only reclaimed by compaction.
+    pub fn remove(&mut self, idx: u32) {
+        if self.is_removed(idx) {
+            return;
+        }
+        if self.removed.len() < self.all.len() {
+            self.removed.resize(self.all.len(), false);
+        }
+        self.removed[idx as usize] = true;
+        self.removed_count += 1;
+        self.removal_log.push(idx);
+        self.holds.remove(&idx);
+        self.free_intervals.remove(&idx);
+        self.secondary_indexes.remove(&self.all[idx as usize]);
+        let offer = &mut self.all[idx as usize];
+        if self.ids.get(&offer.id) == Some(&idx) {
+            self.ids.remove(&offer.id);
+        }
+        self.string_bytes -= offer_string_bytes(offer);
+        offer.id = String::new();
+        offer.data = String::new();
+    }
+
+    /// The `idx` every slot would get if the store was compacted now
+    /// (`u32::MAX` for removed ones).
+    pub fn compacted_positions(&self) -> Vec<u32> {
+        let mut next = 0;
+        (0..self.all.len() as u32)
+            .map(|idx| {
+                if self.is_removed(idx) {
+                    u32::MAX
+                } else {
+                    next += 1;
+                    next - 1
+                }
+            })
+            .collect()
+    }
+
+    /// A copy holding only the live offers, renumbered by `new_idx` as
+    /// returned from [`DenseStore::compacted_positions`].
+    pub fn compacted_copy(&self, new_idx: &[u32]) -> DenseStore {
+        let all = self
+            .all
+            .par_iter()
+            .filter(|offer| new_idx[offer.idx as usize] != u32::MAX)
+            .map(|offer| {
+                let mut offer = offer.clone();
+                offer.idx = new_idx[offer.idx as usize];
+                offer
+            })
+            .collect();
+        let mut store = DenseStore::from_offers(all);
+        store.free_intervals = self
+            .free_intervals
+            .iter()
+            .filter(|(&idx, _)| new_idx[idx as usize] != u32::MAX)
+            .map(|(&idx, intervals)| (new_idx[idx as usize], intervals.clone()))
+            .collect();
+        store
+    }
+
+    pub fn clear(&mut self) {
+        self.all.clear();
+        self.removed.clear();
+        self.removed_count = 0;
+        self.removal_log.clear();
+        self.string_bytes = 0;
+        self.holds.clear();
+        self.free_intervals.clear();
+        self.secondary_indexes.clear();
+        self.ids.clear();
+        self.epoch += 1;
+    }
+
+    /// The `idx` of the live offer with the given ID.
+    pub fn find(&self, id: &str) -> Option<u32> {
+        self.ids.get(id).copied()
+    }
+
+    /// When the earliest hold that is still active lapses, or `u64::MAX`.
+    pub fn next_hold_lapse(&self, now: u64) -> u64 {
+        self.holds
+            .values()
+            .map(|hold| hold.until)
+            .filter(|&until| until > now)
+            .min()
+            .unwrap_or(u64::MAX)
+    }
+
+    pub fn is_held(&self, idx: u32, now: u64) -> bool {
+        self.active_hold(idx, now).is_some()
+    }
+
+    pub fn active_hold(&self, idx: u32, now: u64) -> Option<Hold> {
+        if self.holds.is_empty() {
+            return None;
+        }
+        self.holds
+            .get(&idx)
+            .copied()
+            .filter(|hold| hold.until > now)
+    }
+
+    /// Holds an offer until `until`. Returns `None` if it is already held.
+    pub fn hold(&mut self, idx: u32, until: u64, now: u64) -> Option<Hold> {
+        if self.is_held(idx, now) {
+            return None;
+        }
+        let hold = Hold {
+            until,
+            // Kept within 53 bits so JavaScript clients read it back exactly.
+            token: self.hold_tokens.hash_one((idx, until, self.holds.len())) >> 11,
+        };
+        self.holds.insert(idx, hold);
+        Some(hold)
+    }
+
+    /// Returns false if the offer was not held.
+    pub fn release(&mut self, idx: u32, now: u64) -> bool {
+        self.holds.remove(&idx).is_some_and(|hold| hold.until > now)
+    }
+
+    /// The free intervals of an offer, sorted by start date. For fixed offers
+    /// this is always the whole offer.
+    pub fn free_intervals(&self, idx: u32) -> Vec<(u64, u64)> {
+        match self.free_intervals.get(&idx) {
+            Some(intervals) => intervals.clone(),
+            None => {
+                let offer = &self.all[idx as usize];
+                vec![(offer.start_date, offer.end_date)]
+            }
+        }
+    }
+
+    /// The last moment a rental of the offer can begin: the start date of a
+    /// fixed offer, or the end of the last free interval of a flexible one.
+    pub fn latest_start(&self, idx: u32) -> u64 {
+        let offer = &self.all[idx as usize];
+        if !offer.flexible {
+            return offer.start_date;
+        }
+        self.free_intervals
+            .get(&idx)
+            .and_then(|intervals| intervals.last())
+            .map_or(offer.end_date, |&(_, end)| end)
+    }
+
+    pub fn set_free_intervals(&mut self, idx: u32, intervals: Vec<(u64, u64)>) {
+        self.free_intervals.insert(idx, intervals);
+    }
+
+    /// Drops lapsed holds and returns how many there were.
+    pub fn release_expired_holds(&mut self, now: u64) -> usize {
+        let held = self.holds.len();
+        self.holds.retain(|_, hold| hold.until > now);
+        held - self.holds.len()
+    }
+
     pub fn insert(&mut self, offer: Offer) {
+        self.string_bytes += offer_string_bytes(&offer);
+        self.secondary_indexes.insert(&offer);
+        self.ids.insert(offer.id.clone(), offer.idx);
         self.all.push(offer);
     }
 }
+
+/// Runs `f` with [`tokio::task::block_in_place`] on multi-threaded runtimes,
+/// and directly on others, which cannot hand their tasks to another worker.
+fn block_in_place<R>(f: impl FnOnce() -> R) -> R {
+    match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(f),
+        _ => f(),
+    }
+}
+
+/// Splitting a search only pays off with more than one thread to run on.
+fn default_parallel_threshold() -> usize {
+    if rayon::current_num_threads() > 1 {
+        CONFIG.parallel_search_threshold
+    } else {
+        usize::MAX
+    }
+}
+
+/// Counts the `id` twice, the second time for its key in `DenseStore::ids`.
+fn offer_string_bytes(offer: &Offer) -> usize {
+    offer.id.capacity() + offer.id.len() + offer.data.capacity()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attributes;
+    use crate::car_types::CarType;
+    use crate::currency::Currency;
+    use crate::expiry::ExpiryPolicy;
+    use crate::test_support;
+    use std::sync::Arc;
+
+    fn offer(id: u32, expires_at: u64) -> Offer {
+        Offer {
+            id: id.to_string(),
+            most_specific_region_id: 7 + id % 50,
+            start_date: id as u64,
+            end_date: id as u64 + 1,
+            number_seats: 4,
+            price: id,
+            expires_at,
+            ..test_support::blank_offer()
+        }
+    }
+
+    fn query_all() -> RequestOffer {
+        RequestOffer {
+            region_ids: vec![0],
+            time_range_end: u64::MAX,
+            page_size: 100,
+            price_range_width: 10,
+            min_free_kilometer_width: 10,
+            ..test_support::blank_search()
+        }
+    }
+
+    async fn visible_ids(manager: &DBManager) -> Vec<String> {
+        ids_for_days(manager, 0).await
+    }
+
+    async fn ids_for_days(manager: &DBManager, number_days: u32) -> Vec<String> {
+        let mut query = query_all();
+        query.number_days = number_days;
+        let response = manager.query_for(query).await.unwrap();
+        response.offers.into_iter().map(|offer| offer.ID).collect()
+    }
+
+    #[tokio::test]
+    async fn held_offers_are_hidden_until_released_or_lapsed() {
+        let now = now_millis();
+        let manager = DBManager::new();
+        manager
+            .insert_offers((0..4).map(|id| offer(id, u64::MAX)).collect())
+            .await;
+
+        let hold = manager.hold_offer("1", 60, now).await.unwrap();
+        assert_eq!(hold.until, now + 60_000);
+        assert_eq!(
+            manager.hold_offer("1", 60, now).await,
+            Err(HoldError::AlreadyHeld)
+        );
+        assert_eq!(
+            manager.hold_offer("missing", 60, now).await,
+            Err(HoldError::UnknownOffer)
+        );
+        assert_eq!(visible_ids(&manager).await, ["0", "2", "3"]);
+
+        // Holds follow their offer when compaction moves it.
+        manager.hold_offer("3", 60, now).await.unwrap();
+        {
+            let mut dense_store = manager.dense_store_lock.write().await;
+            let mut index_tree = manager.index_tree_lock.write().await;
+            index_tree.remove_offers([&dense_store.all[0]]);
+            dense_store.remove(0);
+        }
+        assert_eq!(manager.compact().await, 1);
+        assert_eq!(visible_ids(&manager).await, ["2"]);
+
+        assert!(manager.release_offer("3", now).await);
+        assert!(!manager.release_offer("3", now).await);
+        assert_eq!(visible_ids(&manager).await, ["2", "3"]);
+
+        manager.hold_offer("2", 0, now).await.unwrap();
+        assert_eq!(visible_ids(&manager).await, ["2", "3"]);
+        assert_eq!(manager.release_expired_holds(now).await, 1);
+
+        // Only the holder can book a held offer.
+        assert_eq!(
+            manager.book_offer("1", 1, 2, None, now).await,
+            Err(BookingError::Unavailable)
+        );
+        assert_eq!(
+            manager
+                .book_offer("1", 1, 2, Some(hold.token ^ 1), now)
+                .await,
+            Err(BookingError::Unavailable)
+        );
+        assert_eq!(
+            manager.book_offer("1", 1, 2, Some(hold.token), now).await,
+            Ok(vec![])
+        );
+    }
+
+    #[tokio::test]
+    async fn bookings_split_flexible_offers_and_survive_compaction() {
+        const DAY: u64 = 1000 * 60 * 60 * 24;
+        let manager = DBManager::new();
+        let mut window = offer(1, u64::MAX);
+        (window.start_date, window.end_date, window.flexible) = (0, 10 * DAY, true);
+        let mut fixed = offer(2, u64::MAX);
+        (fixed.start_date, fixed.end_date) = (DAY, 3 * DAY);
+        manager
+            .insert_offers(vec![offer(0, 0), window, fixed])
+            .await;
+
+        // A zero-day rental fits into any free interval.
+        assert_eq!(visible_ids(&manager).await, ["0", "1"]);
+        assert_eq!(ids_for_days(&manager, 2).await, ["1", "2"]);
+
+        assert_eq!(
+            manager.book_offer("1", 2 * DAY, 5 * DAY, None, 0).await,
+            Ok(vec![(0, 2 * DAY), (5 * DAY, 10 * DAY)])
+        );
+        assert_eq!(
+            manager.book_offer("1", DAY, 3 * DAY, None, 0).await,
+            Err(BookingError::Unavailable)
+        );
+        assert_eq!(
+            manager.book_offer("2", DAY, 2 * DAY, None, 0).await,
+            Err(BookingError::Unavailable)
+        );
+        assert_eq!(
+            manager.book_offer("2", DAY, 3 * DAY, None, 0).await,
+            Ok(vec![])
+        );
+        assert_eq!(
+            manager.book_offer("2", DAY, 3 * DAY, None, 0).await,
+            Err(BookingError::UnknownOffer)
+        );
+
+        manager.evict(&ExpiryPolicy::default(), 1).await;
+        assert_eq!(manager.compact().await, 2);
+        assert_eq!(ids_for_days(&manager, 5).await, ["1"]);
+        assert!(ids_for_days(&manager, 6).await.is_empty());
+        assert_eq!(
+            manager.book_offer("1", 0, 2 * DAY, None, 0).await,
+            Ok(vec![(5 * DAY, 10 * DAY)])
+        );
+        assert_eq!(ids_for_days(&manager, 5).await, ["1"]);
+        assert!(ids_for_days(&manager, 6).await.is_empty());
+        assert_eq!(ids_for_days(&manager, 1).await, ["1"]);
+    }
+
+    #[tokio::test]
+    async fn converts_prices_into_the_requested_currency() {
+        let manager = DBManager::new();
+        *manager.rates_lock.write().await =
+            ExchangeRates::from_json(br#"{"EUR": 1.0, "USD": 2.0}"#).unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+        let mut offers: Vec<Offer> = [(0, 100, Currency::EUR), (1, 150, usd), (2, 90, usd)]
+            .into_iter()
+            .map(|(id, price, currency)| Offer {
+                price,
+                currency,
+                ..offer(id, u64::MAX)
+            })
+            .collect();
+        offers.push(Offer {
+            currency: "CHF".parse().unwrap(),
+            ..offer(3, u64::MAX)
+        });
+        manager.insert_offers(offers).await;
+
+        let mut query = query_all();
+        query.currency = Some(usd);
+        query.min_price = Some(100);
+        query.price_range_width = 100;
+        let response = manager.query_for(query).await.unwrap();
+        let ids: Vec<_> = response
+            .offers
+            .iter()
+            .map(|offer| offer.ID.as_str())
+            .collect();
+        assert_eq!(ids, ["1", "0"]);
+        let ranges: Vec<_> = response
+            .price_ranges
+            .iter()
+            .map(|range| (range.start, range.count))
+            .collect();
+        // The price facet ignores the price filter itself.
+        assert_eq!(ranges, [(0, 1), (100, 1), (200, 1)]);
+
+        // Without a currency, stored prices are compared as they are.
+        assert_eq!(visible_ids(&manager).await, ["3", "2", "0", "1"]);
+    }
+
+    #[tokio::test]
+    async fn summary_stats_ignore_their_own_filter() {
+        let manager = DBManager::new();
+        let offers = [(0, 100, 10), (1, 300, 20), (2, 500, 30), (3, 200, 0)]
+            .into_iter()
+            .map(|(id, price, free_kilometers)| Offer {
+                price,
+                free_kilometers,
+                ..offer(id, u64::MAX)
+            })
+            .collect();
+        manager.insert_offers(offers).await;
+
+        let mut query = query_all();
+        query.stats = Some("price,freeKilometers".to_string());
+        query.max_price = Some(350);
+        query.min_free_kilometer = Some(10);
+        let stats = manager.query_for(query).await.unwrap().stats;
+        // Offer 2 only fails the price filter and offer 3 only the free
+        // kilometer one, so each still counts towards the other's stats.
+        let price = &stats["price"];
+        assert_eq!(
+            (price.count, price.min, price.max),
+            (3, Some(100), Some(500))
+        );
+        assert_eq!(price.median, Some(300));
+        let free_kilometers = &stats["freeKilometers"];
+        assert_eq!(free_kilometers.count, 3);
+        assert_eq!(free_kilometers.min, Some(0));
+        assert_eq!(free_kilometers.average, Some(10.0));
+    }
+
+    #[tokio::test]
+    async fn counts_total_matches_with_and_without_a_page() {
+        let manager = DBManager::new();
+        let offers = (0..5)
+            .map(|id| Offer {
+                number_seats: 2 + id,
+                ..offer(id, u64::MAX)
+            })
+            .collect();
+        manager.insert_offers(offers).await;
+
+        let mut query = query_all();
+        query.page_size = 1;
+        query.min_number_seats = Some(4);
+        let count = manager.count_for(query).await.unwrap();
+        assert_eq!(count.count, 3);
+        assert!(count.seats_count.is_empty() && count.vollkasko_count.is_none());
+
+        let mut query = query_all();
+        query.min_number_seats = Some(4);
+        query.facets = Some("seatsCount".to_string());
+        let count = manager.count_for(query).await.unwrap();
+        assert_eq!(count.count, 3);
+        // The seats facet ignores the seats filter.
+        assert_eq!(count.seats_count.len(), 5);
+
+        let mut query = query_all();
+        query.min_number_seats = Some(4);
+        query.page_size = 2;
+        query.page = 1;
+        let response = manager.query_for(query).await.unwrap();
+        assert_eq!(response.offers.len(), 1);
+        assert_eq!((response.total_count, response.total_pages), (3, 2));
+    }
+
+    #[tokio::test]
+    async fn counts_offers_per_direct_sub_region() {
+        let manager = DBManager::new();
+        let offers = [(0, 1), (1, 21), (2, 22), (3, 24)]
+            .into_iter()
+            .map(|(id, most_specific_region_id)| Offer {
+                most_specific_region_id,
+                ..offer(id, u64::MAX)
+            })
+            .collect();
+        manager.insert_offers(offers).await;
+
+        let mut query = query_all();
+        query.region_ids = vec![1];
+        query.min_price = Some(1);
+        let response = manager.query_for(query).await.unwrap();
+        let counts: Vec<_> = response
+            .region_counts
+            .iter()
+            .map(|count| (count.region_id, count.count))
+            .collect();
+        // Mitte and Kreuzberg count under Berlin (7), Maxvorstadt under
+        // Munich (8). Offer 0 in Germany (1) itself fails the price filter.
+        assert_eq!(counts, [(7, 2), (8, 1)]);
+    }
+
+    #[tokio::test]
+    async fn filters_and_counts_schema_attributes() {
+        let schema = AttributeSchema::from_json(
+            br#"{"attributes": [
+                {"name": "fuel", "type": "categorical", "values": ["petrol", "electric"]},
+                {"name": "doors", "type": "numeric"}
+            ]}"#,
+        )
+        .unwrap();
+        let manager = DBManager::new().with_schema(schema);
+        let offers = [[1, 4], [1, 2], [0, 4], [0, attributes::MISSING]]
+            .into_iter()
+            .enumerate()
+            .map(|(id, attributes)| Offer {
+                attributes: attributes.into(),
+                ..offer(id as u32, u64::MAX)
+            })
+            .collect();
+        manager.insert_offers(offers).await;
+
+        let mut query = query_all();
+        query.attribute_filters = vec![
+            ("fuel".to_string(), "electric".to_string()),
+            ("doors.min".to_string(), "3".to_string()),
+        ];
+        let response = manager.query_for(query).await.unwrap();
+        let ids: Vec<_> = response
+            .offers
+            .iter()
+            .map(|offer| offer.ID.as_str())
+            .collect();
+        assert_eq!(ids, ["0"]);
+        assert_eq!(response.seats_count.len(), 1);
+
+        // Each attribute's facet ignores its own filter but applies the others.
+        let fuel: Vec<_> = response.attribute_counts[0]
+            .values
+            .iter()
+            .map(|count| (count.value.as_str(), count.count))
+            .collect();
+        assert_eq!(fuel, [("petrol", 1), ("electric", 1)]);
+        let doors: Vec<_> = response.attribute_counts[1]
+            .ranges
+            .iter()
+            .map(|range| (range.start, range.count))
+            .collect();
+        assert_eq!(doors, [(2, 1), (4, 1)]);
+
+        let mut query = query_all();
+        query.attribute_filters = vec![("fuel".to_string(), "diesel".to_string())];
+        assert!(manager.query_for(query).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn memory_stats_track_string_bytes() {
+        let manager = DBManager::from_parts(
+            IndexTree::populate_with_regions(&ROOT_REGION),
+            DenseStore::from_offers(Vec::new()),
+        );
+        let offers: Vec<Offer> = (0..100).map(|id| offer(id, u64::MAX)).collect();
+        let string_bytes: usize = offers.iter().map(offer_string_bytes).sum();
+        manager.insert_offers(offers).await;
+
+        let stats = manager.memory_stats().await;
+        assert_eq!(stats.offers, 100);
+        assert_eq!(stats.string_bytes, string_bytes as u64);
+        assert!(stats.store_bytes >= 100 * std::mem::size_of::<Offer>() as u64);
+        assert!(stats.index_bytes >= 100 * std::mem::size_of::<IndexTreeOffer>() as u64);
+
+        manager.dense_store_lock.write().await.remove(0);
+        let stats = manager.memory_stats().await;
+        assert_eq!(stats.removed_offers, 1);
+        let removed_bytes = offer_string_bytes(&offer(0, u64::MAX));
+        assert_eq!(stats.string_bytes, (string_bytes - removed_bytes) as u64);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn compaction_keeps_store_and_index_consistent_under_writes() {
+        let manager = Arc::new(DBManager::new());
+        manager
+            .insert_offers((0..20_000).map(|id| offer(id, id as u64 % 3)).collect())
+            .await;
+
+        let writer = {
+            let manager = manager.clone();
+            tokio::spawn(async move {
+                for batch in 0..20u32 {
+                    let ids = 20_000 + batch * 100..20_000 + (batch + 1) * 100;
+                    manager
+                        .insert_offers(ids.map(|id| offer(id, u64::MAX)).collect())
+                        .await;
+                    manager.evict(&ExpiryPolicy::default(), 1).await;
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+        let mut reclaimed = 0;
+        for _ in 0..5 {
+            reclaimed += manager.compact().await;
+        }
+        writer.await.unwrap();
+        manager.evict(&ExpiryPolicy::default(), 1).await;
+        reclaimed += manager.compact().await;
+
+        let dense_store = manager.dense_store_lock.read().await;
+        let index_tree = manager.index_tree_lock.read().await;
+        assert_eq!(dense_store.removed_count(), 0);
+        assert_eq!(reclaimed + dense_store.all.len(), 22_000);
+        // Of the first 20 000 offers, only those with `id % 3 == 2` outlive `now == 1`.
+        assert_eq!(dense_store.all.len(), 6_666 + 2_000);
+        for (idx, offer) in dense_store.all.iter().enumerate() {
+            assert_eq!(offer.idx, idx as u32);
+        }
+        let mut indexed = 0;
+        for (_, _, bucket) in index_tree.buckets() {
+            for entry in bucket.iter_sorted() {
+                let offer = &dense_store.all[entry.idx as usize];
+                assert_eq!(entry.start_date, offer.start_date);
+                indexed += 1;
+            }
+        }
+        assert_eq!(indexed, dense_store.all.len());
+    }
+
+    #[tokio::test]
+    async fn cached_searches_are_invalidated_by_writes_to_their_regions() {
+        let manager = DBManager::new();
+        manager
+            .insert_offers(vec![offer(1, u64::MAX), offer(2, u64::MAX)])
+            .await;
+        let munich = || RequestOffer {
+            region_ids: vec![8],
+            ..query_all()
+        };
+        let frankfurt = || RequestOffer {
+            region_ids: vec![9],
+            ..query_all()
+        };
+        let contains = |json: &Bytes, id: &str| {
+            std::str::from_utf8(json)
+                .unwrap()
+                .contains(&format!("\"{}\"", id))
+        };
+        let first = manager.query_json(munich()).await.unwrap();
+        let frankfurt_first = manager.query_json(frankfurt()).await.unwrap();
+        let hits = METRICS.query_cache_hits.load(AtomicOrdering::Relaxed);
+        // A hit shares the cached buffer.
+        assert_eq!(
+            first.as_ptr(),
+            manager.query_json(munich()).await.unwrap().as_ptr()
+        );
+        assert!(METRICS.query_cache_hits.load(AtomicOrdering::Relaxed) > hits);
+
+        // Munich Airport lies in Munich's subtree, but not in Frankfurt's.
+        manager.insert_offers(vec![offer(21, u64::MAX)]).await;
+        let second = manager.query_json(munich()).await.unwrap();
+        assert!(contains(&second, "21") && !contains(&first, "21"));
+        let frankfurt_second = manager.query_json(frankfurt()).await.unwrap();
+        assert_eq!(frankfurt_first.as_ptr(), frankfurt_second.as_ptr());
+
+        manager.hold_offer("1", 60, now_millis()).await.unwrap();
+        assert!(!contains(&manager.query_json(munich()).await.unwrap(), "1"));
+        manager.release_offer("1", now_millis()).await;
+        assert!(contains(&manager.query_json(munich()).await.unwrap(), "1"));
+
+        // A search that started before new rates were set cannot leave its
+        // response behind for searches priced with the new ones.
+        let stale_key = QueryCache::key(&mut munich(), 0);
+        manager.set_rates(ExchangeRates::default()).await;
+        let versions = vec![(8, manager.index_tree_lock.read().await.subtree_version(8))];
+        manager
+            .query_cache
+            .put(stale_key, versions, u64::MAX, Bytes::from_static(b"stale"));
+        assert_ne!(&manager.query_json(munich()).await.unwrap()[..], b"stale");
+    }
+
+    #[tokio::test]
+    async fn unfiltered_counts_match_a_full_scan() {
+        let manager = DBManager::new();
+        let car_types = ["small", "sports", "luxury", "family"];
+        let offers = (0..5_000)
+            .map(|id| Offer {
+                most_specific_region_id: 28,
+                start_date: id as u64 * 1_000,
+                end_date: id as u64 * 1_000 + 1,
+                number_seats: 2 + id % 5,
+                has_vollkasko: id % 3 == 0,
+                car_type: car_types[id as usize % 4].parse().unwrap(),
+                ..offer(id, u64::MAX)
+            })
+            .collect();
+        manager.insert_offers(offers).await;
+
+        let query = || RequestOffer {
+            region_ids: vec![8],
+            time_range_start: 300_500,
+            time_range_end: 4_200_000,
+            facets: Some("carTypeCounts,vollkaskoCount,seatsCount".to_string()),
+            ..query_all()
+        };
+        let summarized = manager.count_for(query()).await.unwrap();
+        // A filter that matches everything forces a scan of every offer.
+        let scanned = manager
+            .count_for(RequestOffer {
+                min_number_seats: Some(0),
+                ..query()
+            })
+            .await
+            .unwrap();
+        assert_eq!(summarized.count, 3_899);
+        assert_eq!(summarized.count, scanned.count);
+        assert_eq!(summarized.car_type_counts, scanned.car_type_counts);
+        assert_eq!(
+            summarized.vollkasko_count.as_ref().unwrap().true_count,
+            scanned.vollkasko_count.as_ref().unwrap().true_count
+        );
+        let seats = |count: &CountResponseModel| {
+            let mut seats: Vec<_> = count
+                .seats_count
+                .iter()
+                .map(|seats| (seats.number_seats, seats.count))
+                .collect();
+            seats.sort();
+            seats
+        };
+        assert_eq!(seats(&summarized), seats(&scanned));
+    }
+
+    #[tokio::test]
+    async fn paged_searches_take_unfiltered_facets_from_summaries() {
+        let manager = DBManager::new();
+        let car_types = ["small", "sports", "luxury", "family"];
+        let offers = (0..5_000)
+            .map(|id| Offer {
+                most_specific_region_id: 28,
+                start_date: id as u64 * 1_000,
+                end_date: id as u64 * 1_000 + 1,
+                number_seats: 2 + id % 5,
+                has_vollkasko: id % 3 == 0,
+                car_type: car_types[id as usize % 4].parse().unwrap(),
+                price: id * 7 % 1_000,
+                ..offer(id, u64::MAX)
+            })
+            .collect();
+        manager.insert_offers(offers).await;
+
+        // All facets and a page, as an ordinary search requests them.
+        let query = || RequestOffer {
+            region_ids: vec![8],
+            time_range_start: 300_500,
+            time_range_end: 4_200_000,
+            page: 2,
+            page_size: 20,
+            ..query_all()
+        };
+        let summarized_searches = || METRICS.summarized_searches.load(AtomicOrdering::Relaxed);
+        let before = summarized_searches();
+        let summarized = manager.query_for(query()).await.unwrap();
+        assert!(summarized_searches() > before);
+        // A filter that matches everything forces a scan of every offer.
+        let scanned = manager
+            .query_for(RequestOffer {
+                min_number_seats: Some(0),
+                ..query()
+            })
+            .await
+            .unwrap();
+
+        let ids = |response: &GetReponseBodyModel| {
+            response
+                .offers
+                .iter()
+                .map(|offer| offer.ID.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(summarized.offers.len(), 20);
+        assert_eq!(ids(&summarized), ids(&scanned));
+        assert_eq!(summarized.total_count, 3_899);
+        assert_eq!(summarized.total_count, scanned.total_count);
+        assert_eq!(summarized.car_type_counts, scanned.car_type_counts);
+        assert_eq!(
+            summarized.vollkasko_count.true_count,
+            scanned.vollkasko_count.true_count
+        );
+        assert_eq!(
+            summarized.vollkasko_count.false_count,
+            scanned.vollkasko_count.false_count
+        );
+        let seats = |response: &GetReponseBodyModel| {
+            let mut seats: Vec<_> = response
+                .seats_count
+                .iter()
+                .map(|seats| (seats.number_seats, seats.count))
+                .collect();
+            seats.sort();
+            seats
+        };
+        assert_eq!(seats(&summarized), seats(&scanned));
+        let prices = |response: &GetReponseBodyModel| {
+            response
+                .price_ranges
+                .iter()
+                .map(|range| (range.start, range.count))
+                .collect::<Vec<_>>()
+        };
+        assert!(!prices(&summarized).is_empty());
+        assert_eq!(prices(&summarized), prices(&scanned));
+    }
+
+    #[tokio::test]
+    async fn bitmap_narrowed_searches_keep_their_facets() {
+        let manager = DBManager::new();
+        let car_types = ["small", "sports", "luxury", "family"];
+        let offers: Vec<Offer> = (0..400)
+            .map(|id| Offer {
+                has_vollkasko: id % 3 == 0,
+                car_type: car_types[id as usize % 4].parse().unwrap(),
+                ..offer(id, u64::MAX)
+            })
+            .collect();
+        manager.insert_offers(offers.clone()).await;
+
+        let sports: CarType = "sports".parse().unwrap();
+        let response = manager
+            .query_for(RequestOffer {
+                car_type: Some(sports),
+                only_vollkasko: Some(true),
+                page_size: 1_000,
+                ..query_all()
+            })
+            .await
+            .unwrap();
+        let matching = |car_type: bool, vollkasko: bool| {
+            offers
+                .iter()
+                .filter(|offer| (offer.car_type == sports) == car_type)
+                .filter(|offer| offer.has_vollkasko == vollkasko)
+                .count() as u32
+        };
+        assert_eq!(response.total_count, matching(true, true));
+        assert_eq!(response.offers.len() as u32, matching(true, true));
+        assert_eq!(response.car_type_counts["sports"], matching(true, true));
+        assert_eq!(response.car_type_counts["small"], 34);
+        assert_eq!(response.vollkasko_count.true_count, matching(true, true));
+        assert_eq!(response.vollkasko_count.false_count, matching(true, false));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn parallel_searches_match_sequential_ones() {
+        let car_types = ["small", "sports", "luxury", "family"];
+        let offers: Vec<Offer> = (0..2_000)
+            .map(|id| Offer {
+                number_seats: 2 + id % 5,
+                has_vollkasko: id % 3 == 0,
+                car_type: car_types[id as usize % 4].parse().unwrap(),
+                price: id * 7_919 % 1_000,
+                free_kilometers: id % 300,
+                ..offer(id, u64::MAX)
+            })
+            .collect();
+        let sequential = DBManager::new().with_parallel_threshold(usize::MAX);
+        let parallel = DBManager::new().with_parallel_threshold(0);
+        sequential.insert_offers(offers.clone()).await;
+        parallel.insert_offers(offers).await;
+
+        let query = || RequestOffer {
+            min_number_seats: Some(4),
+            min_price: Some(100),
+            page: 2,
+            page_size: 7,
+            stats: Some("price,freeKilometers".to_string()),
+            ..query_all()
+        };
+        let response = |mut response: GetReponseBodyModel| {
+            response.seats_count.sort_by_key(|seats| seats.number_seats);
+            sonic_rs::to_string(&response).unwrap()
+        };
+        let expected = response(sequential.query_for(query()).await.unwrap());
+        assert_eq!(
+            response(parallel.query_for(query()).await.unwrap()),
+            expected
+        );
+        assert!(expected.contains("\"offers\":[{"));
+    }
+}
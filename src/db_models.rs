@@ -1,4 +1,5 @@
-use crate::json_models::CarType;
+use crate::car_types::CarType;
+use crate::currency::Currency;
 
 #[derive(Debug, Clone)]
 pub struct Offer {
@@ -11,7 +12,17 @@ pub struct Offer {
     pub end_date: u64,
     pub number_seats: u32,
     pub price: u32,
+    pub currency: Currency,
     pub car_type: CarType,
     pub has_vollkasko: bool,
     pub free_kilometers: u32,
+    /// Unix time in ms after which the offer is evicted, `u64::MAX` if never.
+    pub expires_at: u64,
+    /// `start_date..end_date` is a vehicle's availability window rather than a
+    /// fixed rental: any `numberDays` stretch of it can be booked, and bookings
+    /// split it into the remaining free intervals.
+    pub flexible: bool,
+    /// Values of the attributes declared in [`crate::attributes::SCHEMA`], one
+    /// slot per attribute.
+    pub attributes: Box<[u32]>,
 }
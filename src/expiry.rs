@@ -0,0 +1,166 @@
+use crate::config::CONFIG;
+use crate::db_manager::DBManager;
+use crate::db_models::Offer;
+use crate::metrics::METRICS;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionReason {
+    /// The offer outlived its per-offer or global TTL.
+    Expired,
+    /// The offer can no longer be picked up: the start date of a fixed offer
+    /// has passed, or the last free interval of a flexible one has ended.
+    Started,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EvictionCounts {
+    pub expired: u64,
+    pub started: u64,
+}
+
+impl EvictionCounts {
+    pub fn total(&self) -> u64 {
+        self.expired + self.started
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExpiryPolicy {
+    pub evict_started_offers: bool,
+}
+
+impl ExpiryPolicy {
+    pub fn from_config() -> Self {
+        Self {
+            evict_started_offers: CONFIG.evict_started_offers,
+        }
+    }
+
+    /// `latest_start` is the last moment a rental of the offer can begin, see
+    /// [`crate::db_manager::DenseStore::latest_start`].
+    pub fn eviction_reason(
+        &self,
+        offer: &Offer,
+        latest_start: u64,
+        now: u64,
+    ) -> Option<EvictionReason> {
+        if offer.expires_at <= now {
+            Some(EvictionReason::Expired)
+        } else if self.evict_started_offers && latest_start < now {
+            Some(EvictionReason::Started)
+        } else {
+            None
+        }
+    }
+}
+
+/// Periodically evicts expired offers, compacting the store once enough
+/// slots have been freed.
+pub async fn run_eviction_loop(manager: Arc<DBManager>) {
+    if CONFIG.eviction_interval_secs == 0 {
+        return;
+    }
+    let policy = ExpiryPolicy::from_config();
+    let mut interval = tokio::time::interval(Duration::from_secs(CONFIG.eviction_interval_secs));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let now = now_millis();
+        manager.release_expired_holds(now).await;
+        let counts = manager.evict(&policy, now).await;
+        METRICS.eviction_runs.fetch_add(1, Ordering::Relaxed);
+        METRICS
+            .evicted_expired
+            .fetch_add(counts.expired, Ordering::Relaxed);
+        METRICS
+            .evicted_started
+            .fetch_add(counts.started, Ordering::Relaxed);
+        if counts.total() > 0 {
+            let reclaimed = manager.compact_if_fragmented().await;
+            METRICS.record_compaction(reclaimed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn offer(id: &str, start_date: u64, expires_at: u64) -> Offer {
+        Offer {
+            id: id.to_string(),
+            most_specific_region_id: 7,
+            start_date,
+            end_date: start_date + 1000,
+            expires_at,
+            ..test_support::blank_offer()
+        }
+    }
+
+    #[tokio::test]
+    async fn evicts_expired_and_started_offers() {
+        let now = now_millis();
+        let manager = DBManager::new();
+        manager
+            .insert_offers(vec![
+                offer("keep", now + 10_000, u64::MAX),
+                offer("expired", now + 10_000, now),
+                offer("started", now - 10_000, u64::MAX),
+            ])
+            .await;
+
+        let counts = manager.evict(&ExpiryPolicy::default(), now + 1).await;
+        assert_eq!((counts.expired, counts.started), (1, 0));
+        let policy = ExpiryPolicy {
+            evict_started_offers: true,
+        };
+        let counts = manager.evict(&policy, now + 1).await;
+        assert_eq!((counts.expired, counts.started), (0, 1));
+
+        manager.compact().await;
+        let dense_store = manager.dense_store_lock.read().await;
+        let index_tree = manager.index_tree_lock.read().await;
+        assert_eq!(dense_store.all.len(), 1);
+        assert_eq!(dense_store.all[0].id, "keep");
+        assert_eq!(dense_store.all[0].idx, 0);
+        let remaining: Vec<u32> = index_tree.get_available_offers(0, 0, 0, u64::MAX).collect();
+        assert_eq!(remaining, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn keeps_flexible_offers_until_their_last_free_interval_ends() {
+        let now = now_millis();
+        let manager = DBManager::new();
+        let flexible = |id| {
+            let mut offer = offer(id, now - 20_000, u64::MAX);
+            (offer.end_date, offer.flexible) = (now + 20_000, true);
+            offer
+        };
+        manager
+            .insert_offers(vec![flexible("window"), flexible("booked")])
+            .await;
+        manager
+            .book_offer("booked", now - 1_000, now + 20_000, None, now)
+            .await
+            .unwrap();
+
+        let policy = ExpiryPolicy {
+            evict_started_offers: true,
+        };
+        let counts = manager.evict(&policy, now).await;
+        assert_eq!((counts.expired, counts.started), (0, 1));
+        let dense_store = manager.dense_store_lock.read().await;
+        assert_eq!(dense_store.find("window"), Some(0));
+        assert_eq!(dense_store.find("booked"), None);
+    }
+}
@@ -0,0 +1,76 @@
+/// Facets a search computes, chosen with `facets=`: a comma-separated list
+/// of response field names, `none`, or `all` (the default). Facets that are
+/// not selected are returned empty and cost nothing to evaluate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FacetSelection {
+    pub price_ranges: bool,
+    pub car_types: bool,
+    pub seats: bool,
+    pub free_kilometers: bool,
+    pub vollkasko: bool,
+    pub attributes: bool,
+    pub regions: bool,
+}
+
+impl FacetSelection {
+    pub const ALL: FacetSelection = FacetSelection {
+        price_ranges: true,
+        car_types: true,
+        seats: true,
+        free_kilometers: true,
+        vollkasko: true,
+        attributes: true,
+        regions: true,
+    };
+
+    pub const NONE: FacetSelection = FacetSelection {
+        price_ranges: false,
+        car_types: false,
+        seats: false,
+        free_kilometers: false,
+        vollkasko: false,
+        attributes: false,
+        regions: false,
+    };
+
+    pub fn parse(spec: Option<&str>) -> Result<Self, &'static str> {
+        let spec = match spec {
+            None | Some("all") => return Ok(Self::ALL),
+            Some("none") => return Ok(Self::NONE),
+            Some(spec) => spec.replace("%2C", ","),
+        };
+        let mut selection = Self::NONE;
+        for facet in spec.split(',') {
+            match facet {
+                "priceRanges" => selection.price_ranges = true,
+                "carTypeCounts" => selection.car_types = true,
+                "seatsCount" => selection.seats = true,
+                "freeKilometerRange" => selection.free_kilometers = true,
+                "vollkaskoCount" => selection.vollkasko = true,
+                "attributeCounts" => selection.attributes = true,
+                "regionCounts" => selection.regions = true,
+                _ => return Err("Unknown facet"),
+            }
+        }
+        Ok(selection)
+    }
+
+    pub fn is_none(&self) -> bool {
+        *self == Self::NONE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_facet_lists() {
+        assert_eq!(FacetSelection::parse(None), Ok(FacetSelection::ALL));
+        assert!(FacetSelection::parse(Some("none")).unwrap().is_none());
+        let selection = FacetSelection::parse(Some("priceRanges%2CvollkaskoCount")).unwrap();
+        assert!(selection.price_ranges && selection.vollkasko);
+        assert!(!selection.car_types && !selection.seats);
+        assert!(FacetSelection::parse(Some("priceRanges,colours")).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use serde::Deserialize;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+/// Half the earth's circumference; larger radii would match everything.
+const MAX_RADIUS_KM: f64 = 20_038.0;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl GeoPoint {
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.lat) && (-180.0..=180.0).contains(&self.lon)
+    }
+
+    /// Great-circle distance by the haversine formula.
+    pub fn distance_km(&self, other: &GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.lon - self.lon).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// Restricts a search to regions within `radius_km` of `center`, given as
+/// `near=<lat>,<lon>&radiusKm=<km>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoFilter {
+    pub center: GeoPoint,
+    pub radius_km: f64,
+}
+
+impl GeoFilter {
+    pub fn parse(near: Option<&str>, radius_km: Option<f64>) -> Result<Option<Self>, &'static str> {
+        let (near, radius_km) = match (near, radius_km) {
+            (None, None) => return Ok(None),
+            (Some(near), Some(radius_km)) => (near.replace("%2C", ","), radius_km),
+            _ => return Err("'near' and 'radiusKm' must be given together"),
+        };
+        let (lat, lon) = near.split_once(',').ok_or("Invalid 'near' coordinates")?;
+        let center = GeoPoint {
+            lat: lat.parse().map_err(|_| "Invalid 'near' coordinates")?,
+            lon: lon.parse().map_err(|_| "Invalid 'near' coordinates")?,
+        };
+        if !center.is_valid() {
+            return Err("Invalid 'near' coordinates");
+        }
+        if !(radius_km > 0.0 && radius_km <= MAX_RADIUS_KM) {
+            return Err("Invalid 'radiusKm'");
+        }
+        Ok(Some(Self { center, radius_km }))
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, point: &GeoPoint) -> bool {
+        self.center.distance_km(point) <= self.radius_km
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_great_circle_distances() {
+        let munich = GeoPoint {
+            lat: 48.137,
+            lon: 11.575,
+        };
+        let munich_airport = GeoPoint {
+            lat: 48.353,
+            lon: 11.786,
+        };
+        let distance = munich.distance_km(&munich_airport);
+        assert!((28.0..30.0).contains(&distance), "{}", distance);
+        assert_eq!(munich.distance_km(&munich), 0.0);
+    }
+
+    #[test]
+    fn parses_radius_searches() {
+        let filter = GeoFilter::parse(Some("48.353%2C11.786"), Some(30.0))
+            .unwrap()
+            .unwrap();
+        assert_eq!(filter.center.lon, 11.786);
+        assert_eq!(GeoFilter::parse(None, None), Ok(None));
+        for (near, radius_km) in [
+            (Some("48.3,11.7"), None),
+            (None, Some(30.0)),
+            (Some("91,0"), Some(30.0)),
+            (Some("48.3"), Some(30.0)),
+            (Some("48.3,11.7"), Some(0.0)),
+            (Some("48.3,11.7"), Some(f64::NAN)),
+        ] {
+            assert!(GeoFilter::parse(near, radius_km).is_err(), "{:?}", near);
+        }
+    }
+}
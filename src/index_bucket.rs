@@ -0,0 +1,307 @@
+use crate::block_summary::{summarize_blocks, Block, FacetSummary, BLOCK_SIZE};
+use crate::index_tree::IndexTreeOffer;
+use itertools::Itertools;
+
+/// Inserts land in a small sorted buffer first; only a full buffer is merged
+/// into the runs, so the memmove of an insert is bounded by this size.
+const BUFFER_CAPACITY: usize = 256;
+
+/// The offers of one region/duration bucket, ordered by start date.
+///
+/// Offers are kept in a log-structured set of sorted runs: a full insert
+/// buffer becomes a new run, and whenever the newest run is at least as large
+/// as the one before it, the two are merged. Run sizes therefore behave like
+/// a binary counter, giving amortized O(log n) inserts and at most log2(n)
+/// runs to binary-search per range scan.
+///
+/// Each run keeps facet counts per block of [`BLOCK_SIZE`] entries, so
+/// unfiltered facets over a range only scan the blocks at its edges.
+#[derive(Default, Debug, Clone)]
+pub(crate) struct IndexBucket {
+    buffer: Vec<IndexTreeOffer>,
+    /// Sorted runs, largest first.
+    runs: Vec<Run>,
+}
+
+#[derive(Debug, Clone)]
+struct Run {
+    entries: Vec<IndexTreeOffer>,
+    blocks: Vec<Block>,
+}
+
+impl Run {
+    fn new(entries: Vec<IndexTreeOffer>) -> Self {
+        let blocks = summarize_blocks(&entries);
+        Run { entries, blocks }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl IndexBucket {
+    pub(crate) fn from_sorted(offers: Vec<IndexTreeOffer>) -> Self {
+        debug_assert!(offers.is_sorted_by_key(|offer| offer.start_date));
+        IndexBucket {
+            buffer: Vec::new(),
+            runs: if offers.is_empty() {
+                vec![]
+            } else {
+                vec![Run::new(offers)]
+            },
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.buffer.len() + self.runs.iter().map(Run::len).sum::<usize>()
+    }
+
+    /// Heap memory held by the bucket's entries and block summaries.
+    pub(crate) fn memory_bytes(&self) -> usize {
+        let entries = self.buffer.capacity()
+            + self
+                .runs
+                .iter()
+                .map(|run| run.entries.capacity())
+                .sum::<usize>();
+        let blocks: usize = self
+            .runs
+            .iter()
+            .flat_map(|run| &run.blocks)
+            .map(|block| {
+                std::mem::size_of::<Block>()
+                    + block.facets.car_types.capacity() * std::mem::size_of::<u32>()
+                    + block.facets.seats.capacity() * std::mem::size_of::<(u32, u32)>()
+            })
+            .sum();
+        entries * std::mem::size_of::<IndexTreeOffer>()
+            + blocks
+            + self.runs.capacity() * std::mem::size_of::<Run>()
+    }
+
+    pub(crate) fn insert(&mut self, offer: IndexTreeOffer) {
+        let idx = self
+            .buffer
+            .partition_point(|other| other.start_date <= offer.start_date);
+        self.buffer.insert(idx, offer);
+
+        if self.buffer.len() >= BUFFER_CAPACITY {
+            let run = std::mem::replace(&mut self.buffer, Vec::with_capacity(BUFFER_CAPACITY));
+            self.runs.push(Run::new(run));
+            while self.runs.len() >= 2
+                && self.runs[self.runs.len() - 1].len() >= self.runs[self.runs.len() - 2].len()
+            {
+                let newer = self.runs.pop().unwrap();
+                let older = self.runs.pop().unwrap();
+                self.runs
+                    .push(Run::new(merge_runs(older.entries, newer.entries)));
+            }
+        }
+    }
+
+    /// Adds many offers at once and collapses the bucket into a single run.
+    pub(crate) fn extend(&mut self, mut offers: Vec<IndexTreeOffer>) {
+        offers.sort_by_key(|offer| offer.start_date);
+        let merged = self
+            .iter_sorted()
+            .copied()
+            .merge_by(offers, |a, b| a.start_date <= b.start_date)
+            .collect();
+        *self = IndexBucket::from_sorted(merged);
+    }
+
+    /// Drops all entries for which `keep` returns false. Runs stay sorted.
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&IndexTreeOffer) -> bool) {
+        self.buffer.retain(&mut keep);
+        for run in &mut self.runs {
+            let len = run.len();
+            run.entries.retain(&mut keep);
+            if run.len() != len {
+                run.blocks = summarize_blocks(&run.entries);
+            }
+        }
+        self.runs.retain(|run| run.len() > 0);
+    }
+
+    /// Rewrites the `idx` of every entry; start dates are left untouched.
+    pub(crate) fn remap(&mut self, new_idx: impl Fn(u32) -> u32) {
+        for offer in self
+            .runs
+            .iter_mut()
+            .flat_map(|run| &mut run.entries)
+            .chain(&mut self.buffer)
+        {
+            offer.idx = new_idx(offer.idx);
+        }
+    }
+
+    /// Offers with `start <= start_date <= end`. Each run yields its offers in
+    /// start-date order, but the runs are not merged with each other.
+    pub(crate) fn range(&self, start: u64, end: u64) -> impl Iterator<Item = &IndexTreeOffer> {
+        self.runs
+            .iter()
+            .map(|run| &run.entries)
+            .chain(std::iter::once(&self.buffer))
+            .flat_map(move |run| {
+                let start_idx = run.partition_point(|offer| offer.start_date < start);
+                run[start_idx..]
+                    .iter()
+                    .take_while(move |offer| offer.start_date <= end)
+            })
+    }
+
+    /// Adds the facets of the entries with `start <= start_date <= end` and
+    /// `end_date <= max_end` to `summary`. Blocks that lie entirely inside
+    /// the range are added from their counts; the matching entries of the
+    /// others, and of the insert buffer, are passed to `edge` instead.
+    pub(crate) fn summarize_range(
+        &self,
+        start: u64,
+        end: u64,
+        max_end: u64,
+        summary: &mut FacetSummary,
+        edge: &mut impl FnMut(&IndexTreeOffer),
+    ) {
+        let matches = |entry: &&IndexTreeOffer| {
+            (start..=end).contains(&entry.start_date) && entry.end_date <= max_end
+        };
+        for run in &self.runs {
+            let first = run.blocks.partition_point(|block| block.max_start < start);
+            for (position, block) in run.blocks.iter().enumerate().skip(first) {
+                if block.min_start > end {
+                    break;
+                }
+                if block.exact
+                    && start <= block.min_start
+                    && block.max_start <= end
+                    && block.max_end <= max_end
+                {
+                    summary.merge(&block.facets);
+                } else {
+                    let entries = &run.entries[position * BLOCK_SIZE..];
+                    entries[..entries.len().min(BLOCK_SIZE)]
+                        .iter()
+                        .filter(matches)
+                        .for_each(&mut *edge);
+                }
+            }
+        }
+        self.buffer.iter().filter(matches).for_each(edge);
+    }
+
+    /// All offers in start-date order.
+    pub(crate) fn iter_sorted(&self) -> impl Iterator<Item = &IndexTreeOffer> {
+        self.runs
+            .iter()
+            .map(|run| &run.entries)
+            .chain(std::iter::once(&self.buffer))
+            .kmerge_by(|a, b| a.start_date < b.start_date)
+    }
+}
+
+fn merge_runs(older: Vec<IndexTreeOffer>, newer: Vec<IndexTreeOffer>) -> Vec<IndexTreeOffer> {
+    let mut merged = Vec::with_capacity(older.len() + newer.len());
+    merged.extend(
+        older
+            .into_iter()
+            .merge_by(newer, |a, b| a.start_date <= b.start_date),
+    );
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_summary::FacetKey;
+
+    fn entry(start_date: u64, idx: u32) -> IndexTreeOffer {
+        IndexTreeOffer {
+            start_date,
+            end_date: start_date + 1,
+            idx,
+            facet_key: FacetKey::default(),
+        }
+    }
+
+    #[test]
+    fn keeps_runs_sorted_and_logarithmic() {
+        let mut bucket = IndexBucket::default();
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for idx in 0..100_000u32 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            bucket.insert(entry(state % 1_000_000, idx));
+        }
+
+        assert_eq!(bucket.len(), 100_000);
+        assert!(bucket.runs.len() <= 17);
+        for run in bucket
+            .runs
+            .iter()
+            .map(|run| &run.entries)
+            .chain([&bucket.buffer])
+        {
+            assert!(run.is_sorted_by_key(|offer| offer.start_date));
+        }
+        assert!(bucket
+            .iter_sorted()
+            .is_sorted_by_key(|offer| offer.start_date));
+
+        let mut expected: Vec<u32> = bucket
+            .iter_sorted()
+            .filter(|offer| (1_000..=5_000).contains(&offer.start_date))
+            .map(|offer| offer.idx)
+            .collect();
+        let mut results: Vec<u32> = bucket.range(1_000, 5_000).map(|offer| offer.idx).collect();
+        expected.sort();
+        results.sort();
+        assert_eq!(results, expected);
+    }
+
+    #[test]
+    fn extend_collapses_into_one_run() {
+        let mut bucket = IndexBucket::default();
+        for idx in 0..1_000 {
+            bucket.insert(entry(1_000 - idx as u64, idx));
+        }
+        bucket.extend(
+            (1_000..1_500)
+                .map(|idx| entry(idx as u64 % 700, idx))
+                .collect(),
+        );
+
+        assert_eq!(bucket.len(), 1_500);
+        assert!(bucket.buffer.is_empty());
+        assert_eq!(bucket.runs.len(), 1);
+        assert!(bucket.runs[0]
+            .entries
+            .is_sorted_by_key(|offer| offer.start_date));
+    }
+
+    #[test]
+    fn sums_covered_blocks_and_scans_the_edges() {
+        let mut bucket = IndexBucket::default();
+        for idx in 0..10_000u32 {
+            let start_date = (idx as u64 * 7_919) % 10_000;
+            bucket.insert(entry(start_date, idx));
+        }
+
+        let (start, end) = (1_500, 8_200);
+        let mut summary = FacetSummary::default();
+        let mut edges = 0;
+        bucket.summarize_range(start, end, end, &mut summary, &mut |_| edges += 1);
+        let matching = bucket
+            .iter_sorted()
+            .filter(|offer| (start..=end).contains(&offer.start_date) && offer.end_date <= end)
+            .count();
+        assert_eq!(summary.len as usize + edges, matching);
+        assert!(
+            summary.len > 0 && edges < matching / 2,
+            "{} of {}",
+            edges,
+            matching
+        );
+    }
+}
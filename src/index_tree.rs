@@ -1,31 +1,69 @@
+use crate::block_summary::{FacetKey, FacetSummary};
 use crate::db_models::Offer;
-use fxhash::FxHashMap;
+use crate::geo::{GeoFilter, GeoPoint};
+use crate::index_bucket::IndexBucket;
+use fxhash::{FxHashMap, FxHashSet};
 use once_cell::sync::Lazy;
+use rayon::prelude::*;
 use serde::Deserialize;
 use serde_json::json;
 
-#[derive(Default, Debug)]
-struct IndexTreeOffer {
-    start_date: u64,
-    end_date: u64,
-    idx: u32,
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IndexTreeOffer {
+    pub(crate) start_date: u64,
+    pub(crate) end_date: u64,
+    pub(crate) idx: u32,
+    pub(crate) facet_key: FacetKey,
 }
 
-#[derive(Default, Debug)]
+impl From<&Offer> for IndexTreeOffer {
+    fn from(offer: &Offer) -> Self {
+        IndexTreeOffer {
+            start_date: offer.start_date,
+            end_date: offer.end_date,
+            idx: offer.idx,
+            facet_key: offer.into(),
+        }
+    }
+}
+
+const DAY_MS: u64 = 1000 * 60 * 60 * 24;
+
+/// Key of the per-region bucket an offer is stored in: its length in whole days.
+fn duration_days(offer: &Offer) -> u32 {
+    days_between(offer.start_date, offer.end_date)
+}
+
+fn days_between(start_date: u64, end_date: u64) -> u32 {
+    ((end_date - start_date) / DAY_MS) as u32
+}
+
+#[derive(Default, Debug, Clone)]
 struct IndexTreeElement {
-    offers: FxHashMap<u32, Vec<IndexTreeOffer>>,
+    offers: FxHashMap<u32, IndexBucket>,
+    /// Free intervals of flexible offers, keyed by their length in whole days.
+    /// An offer has one entry per interval.
+    windows: FxHashMap<u32, IndexBucket>,
     sub_regions: Option<Vec<u8>>,
+    /// `None` for ids that are not part of the region tree.
+    name: Option<String>,
+    parent: Option<u8>,
+    /// The region's own coordinates, if the region file gives any.
+    location: Option<GeoPoint>,
+    /// Bumped whenever the offers of the region or of a descendant change.
+    subtree_version: u64,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct IndexTree {
     regions: Vec<IndexTreeElement>,
 }
 
 impl IndexTree {
     pub fn populate_with_regions(root: &Region) -> IndexTree {
-        let mut tree = IndexTree::default();
-        tree.regions = Vec::with_capacity(125);
+        let mut tree = IndexTree {
+            regions: Vec::with_capacity(125),
+        };
         for _ in 0..125 {
             tree.regions.push(IndexTreeElement::default());
         }
@@ -34,15 +72,76 @@ impl IndexTree {
     }
 
     fn populate_with_regions_recursive(&mut self, region: &Region) {
+        let element = &mut self.regions[region.id as usize];
+        element.name = Some(region.name.clone());
+        element.location = match (region.lat, region.lon) {
+            (Some(lat), Some(lon)) => Some(GeoPoint { lat, lon }),
+            _ => None,
+        };
         for subregion in &region.subregions {
+            self.regions[subregion.id as usize].parent = Some(region.id);
             self.regions[region.id as usize]
                 .sub_regions
-                .get_or_insert_with(|| Vec::new())
+                .get_or_insert_with(Vec::new)
                 .push(subregion.id);
             self.populate_with_regions_recursive(subregion);
         }
     }
 
+    pub fn contains_region(&self, region_id: u8) -> bool {
+        (region_id as usize) < self.regions.len()
+    }
+
+    pub fn subtree_version(&self, region_id: u8) -> u64 {
+        self.regions[region_id as usize].subtree_version
+    }
+
+    /// Marks the offers of a region as changed, invalidating cached searches
+    /// that cover it. Changes made through the tree do this themselves.
+    pub fn touch_region(&mut self, region_id: u8) {
+        let mut current = Some(region_id);
+        while let Some(region_id) = current {
+            let region = &mut self.regions[region_id as usize];
+            region.subtree_version += 1;
+            current = region.parent;
+        }
+    }
+
+    /// Moves every subtree version past the one in `other`, so that no search
+    /// cached against `other` is served from this tree.
+    pub fn advance_versions_past(&mut self, other: &IndexTree) {
+        for (region, other) in self.regions.iter_mut().zip(&other.regions) {
+            region.subtree_version = region.subtree_version.max(other.subtree_version) + 1;
+        }
+    }
+
+    pub fn region_name(&self, region_id: u8) -> Option<&str> {
+        self.regions.get(region_id as usize)?.name.as_deref()
+    }
+
+    /// Whether the region lies within the radius of `filter`. Regions without
+    /// coordinates of their own never do.
+    pub fn region_within(&self, region_id: u8, filter: &GeoFilter) -> bool {
+        self.regions[region_id as usize]
+            .location
+            .is_some_and(|location| filter.contains(&location))
+    }
+
+    /// The region and its ancestors, starting at the root.
+    pub fn region_path(&self, region_id: u8) -> Option<Vec<(u8, &str)>> {
+        let mut path = Vec::new();
+        let mut current = Some(region_id);
+        while let Some(region_id) = current {
+            path.push((region_id, self.region_name(region_id)?));
+            current = self.regions[region_id as usize].parent;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Offers of exactly `number_of_days` that lie within the time range,
+    /// followed by the flexible offers with a free interval that fits a
+    /// rental of `number_of_days` inside the time range.
     pub fn get_available_offers(
         &self,
         region_id: u8,
@@ -50,64 +149,412 @@ impl IndexTree {
         time_range_start: u64,
         time_range_end: u64,
     ) -> impl Iterator<Item = u32> + '_ {
-        let mut stack = vec![region_id];
+        self.get_available_offers_in(
+            self.resolve_regions(&[region_id], &[]),
+            number_of_days,
+            time_range_start,
+            time_range_end,
+        )
+    }
 
-        std::iter::from_fn(move || {
-            while let Some(current_region_id) = stack.pop() {
-                let region = &self.regions[current_region_id as usize];
+    /// Like [`Self::get_available_offers`], over regions resolved with
+    /// [`Self::resolve_regions`].
+    pub fn get_available_offers_in(
+        &self,
+        regions: Vec<u8>,
+        number_of_days: u32,
+        time_range_start: u64,
+        time_range_end: u64,
+    ) -> impl Iterator<Item = u32> + '_ {
+        self.fixed_offers(
+            regions.clone(),
+            number_of_days,
+            time_range_start,
+            time_range_end,
+        )
+        .chain(self.available_windows(
+            regions,
+            number_of_days,
+            time_range_start,
+            time_range_end,
+        ))
+    }
 
-                if let Some(sub_regions) = &region.sub_regions {
+    /// An upper bound of the index entries a search of `regions` for rentals
+    /// of `number_of_days` walks, regardless of its time range.
+    pub fn candidate_estimate(&self, regions: &[u8], number_of_days: u32) -> usize {
+        regions
+            .iter()
+            .map(|&region_id| {
+                let region = &self.regions[region_id as usize];
+                let fixed = region
+                    .offers
+                    .get(&number_of_days)
+                    .map_or(0, IndexBucket::len);
+                let windows: usize = region
+                    .windows
+                    .iter()
+                    .filter(|(days, _)| **days >= number_of_days)
+                    .map(|(_, bucket)| bucket.len())
+                    .sum();
+                fixed + windows
+            })
+            .sum()
+    }
+
+    /// The regions covered by the subtrees of `included` but not by those of
+    /// `excluded`, each listed once even if the included subtrees overlap.
+    pub fn resolve_regions(&self, included: &[u8], excluded: &[u8]) -> Vec<u8> {
+        let mut visited = vec![false; self.regions.len()];
+        let mut walk = |roots: &[u8], regions: &mut Vec<u8>| {
+            let mut stack = roots.to_vec();
+            while let Some(region_id) = stack.pop() {
+                if std::mem::replace(&mut visited[region_id as usize], true) {
+                    continue;
+                }
+                regions.push(region_id);
+                if let Some(sub_regions) = &self.regions[region_id as usize].sub_regions {
                     stack.extend(sub_regions.iter().copied());
                 }
+            }
+        };
+        // Marking the excluded subtrees first also excludes included regions
+        // that lie inside them.
+        walk(excluded, &mut Vec::new());
+        let mut regions = Vec::new();
+        walk(included, &mut regions);
+        regions
+    }
 
-                if let Some(offers) = region.offers.get(&number_of_days) {
-                    let start_idx =
-                        offers.partition_point(|offer| offer.start_date < time_range_start);
-
-                    let offer_iter = offers[start_idx..]
-                        .iter()
-                        .take_while(move |offer| offer.start_date <= time_range_end)
-                        .filter(move |offer| offer.end_date <= time_range_end)
-                        .map(|offer| offer.idx);
-
-                    return Some(offer_iter);
+    /// Maps each region to the region it is counted under in the
+    /// `regionCounts` facet of a search over `included`: the direct
+    /// sub-region of an included region it lies in, or the included region
+    /// itself. Regions outside the search map to `u8::MAX`. An included
+    /// region inside another one is grouped like the rest of the outer one,
+    /// whatever the order of `included`.
+    pub fn region_groups(&self, included: &[u8]) -> Vec<u8> {
+        let mut groups = vec![u8::MAX; self.regions.len()];
+        for &root in included {
+            if groups[root as usize] != u8::MAX || self.has_ancestor_in(root, included) {
+                continue;
+            }
+            groups[root as usize] = root;
+            for &child in self.regions[root as usize].sub_regions.iter().flatten() {
+                let mut stack = vec![child];
+                while let Some(region_id) = stack.pop() {
+                    if groups[region_id as usize] != u8::MAX {
+                        continue;
+                    }
+                    groups[region_id as usize] = child;
+                    if let Some(sub_regions) = &self.regions[region_id as usize].sub_regions {
+                        stack.extend(sub_regions.iter().copied());
+                    }
                 }
             }
+        }
+        groups
+    }
 
-            None
-        })
-        .flatten()
+    fn has_ancestor_in(&self, region_id: u8, regions: &[u8]) -> bool {
+        let mut current = self.regions[region_id as usize].parent;
+        while let Some(region_id) = current {
+            if regions.contains(&region_id) {
+                return true;
+            }
+            current = self.regions[region_id as usize].parent;
+        }
+        false
     }
 
-    pub fn insert_offer(&mut self, region_id: u8, offer: &Offer) {
-        self.regions[region_id as usize]
-            .offers
-            .entry(((offer.end_date - offer.start_date) / (1000 * 60 * 60 * 24)) as u32)
-            .and_modify(|v| {
-                let idx = v
-                    .binary_search_by_key(&offer.start_date, |offer| offer.start_date)
-                    .unwrap_or_else(|x| x);
-                v.insert(
-                    idx,
-                    IndexTreeOffer {
-                        start_date: offer.start_date,
-                        end_date: offer.end_date,
-                        idx: offer.idx,
-                    },
+    /// Adds the facets of the fixed offers [`Self::get_available_offers_in`]
+    /// yields to `summary`, without visiting the offers of index blocks that
+    /// lie entirely in the time range. The others are passed to `edge`.
+    pub(crate) fn summarize_fixed_offers(
+        &self,
+        regions: &[u8],
+        number_of_days: u32,
+        time_range_start: u64,
+        time_range_end: u64,
+        summary: &mut FacetSummary,
+        mut edge: impl FnMut(u32),
+    ) {
+        for &region_id in regions {
+            if let Some(offers) = self.regions[region_id as usize].offers.get(&number_of_days) {
+                offers.summarize_range(
+                    time_range_start,
+                    time_range_end,
+                    time_range_end,
+                    summary,
+                    &mut |offer| edge(offer.idx),
                 );
+            }
+        }
+    }
+
+    fn fixed_offers(
+        &self,
+        regions: Vec<u8>,
+        number_of_days: u32,
+        time_range_start: u64,
+        time_range_end: u64,
+    ) -> impl Iterator<Item = u32> + '_ {
+        regions
+            .into_iter()
+            .filter_map(move |region_id| {
+                self.regions[region_id as usize].offers.get(&number_of_days)
+            })
+            .flat_map(move |offers| {
+                offers
+                    .range(time_range_start, time_range_end)
+                    .filter(move |offer| offer.end_date <= time_range_end)
+                    .map(|offer| offer.idx)
             })
-            .or_insert_with(|| {
-                vec![IndexTreeOffer {
-                    start_date: offer.start_date,
-                    end_date: offer.end_date,
+    }
+
+    pub(crate) fn available_windows(
+        &self,
+        regions: Vec<u8>,
+        number_of_days: u32,
+        time_range_start: u64,
+        time_range_end: u64,
+    ) -> impl Iterator<Item = u32> + '_ {
+        let rental_length = number_of_days as u64 * DAY_MS;
+        let mut seen = FxHashSet::default();
+        regions
+            .into_iter()
+            .map(|region_id| &self.regions[region_id as usize])
+            .flat_map(move |region| {
+                region
+                    .windows
+                    .iter()
+                    .filter(move |(days, _)| **days >= number_of_days)
+                    .flat_map(move |(days, bucket)| {
+                        // Intervals in this bucket are shorter than `days + 1`
+                        // days, so earlier ones end before a rental could fit.
+                        let earliest_start = time_range_start
+                            .saturating_add(rental_length)
+                            .saturating_sub((*days as u64 + 1) * DAY_MS);
+                        let latest_start = time_range_end.saturating_sub(rental_length);
+                        bucket
+                            .range(earliest_start, latest_start)
+                            .filter(move |window| {
+                                window
+                                    .start_date
+                                    .max(time_range_start)
+                                    .saturating_add(rental_length)
+                                    <= window.end_date.min(time_range_end)
+                            })
+                    })
+            })
+            // An offer may have several free intervals that fit.
+            .filter(move |window| seen.insert(window.idx))
+            .map(|window| window.idx)
+    }
+
+    pub fn insert_offer(&mut self, region_id: u8, offer: &Offer) {
+        self.touch_region(region_id);
+        let region = &mut self.regions[region_id as usize];
+        let buckets = if offer.flexible {
+            &mut region.windows
+        } else {
+            &mut region.offers
+        };
+        buckets
+            .entry(duration_days(offer))
+            .or_default()
+            .insert(offer.into());
+    }
+
+    /// Replaces the indexed free intervals of a flexible offer.
+    pub fn replace_free_intervals(
+        &mut self,
+        offer: &Offer,
+        old_intervals: &[(u64, u64)],
+        new_intervals: &[(u64, u64)],
+    ) {
+        self.touch_region(offer.most_specific_region_id as u8);
+        let windows = &mut self.regions[offer.most_specific_region_id as usize].windows;
+        for &(start_date, end_date) in old_intervals {
+            if let Some(bucket) = windows.get_mut(&days_between(start_date, end_date)) {
+                bucket.retain(|window| window.idx != offer.idx || window.start_date != start_date);
+            }
+        }
+        for &(start_date, end_date) in new_intervals {
+            windows
+                .entry(days_between(start_date, end_date))
+                .or_default()
+                .insert(IndexTreeOffer {
+                    start_date,
+                    end_date,
                     idx: offer.idx,
-                }]
-            });
+                    facet_key: offer.into(),
+                });
+        }
+    }
+
+    /// Inserts many offers at once, each into the region given by its
+    /// `most_specific_region_id`. New entries are grouped per bucket, and the
+    /// touched buckets are then sorted and merged in parallel.
+    pub fn bulk_insert(&mut self, offers: &[Offer]) {
+        let mut pending: Vec<FxHashMap<(bool, u32), Vec<IndexTreeOffer>>> =
+            self.regions.iter().map(|_| FxHashMap::default()).collect();
+        for offer in offers {
+            pending[offer.most_specific_region_id as usize]
+                .entry((offer.flexible, duration_days(offer)))
+                .or_default()
+                .push(offer.into());
+        }
+        for (region_id, buckets) in pending.iter().enumerate() {
+            if !buckets.is_empty() {
+                self.touch_region(region_id as u8);
+            }
+        }
+
+        let mut work = Vec::new();
+        for (region, mut pending) in self.regions.iter_mut().zip(pending) {
+            for (flexible, days) in pending.keys() {
+                if *flexible {
+                    region.windows.entry(*days).or_default();
+                } else {
+                    region.offers.entry(*days).or_default();
+                }
+            }
+            let fixed = region
+                .offers
+                .iter_mut()
+                .map(|(days, bucket)| (false, days, bucket));
+            let windows = region
+                .windows
+                .iter_mut()
+                .map(|(days, bucket)| (true, days, bucket));
+            work.extend(fixed.chain(windows).filter_map(|(flexible, days, bucket)| {
+                Some((bucket, pending.remove(&(flexible, *days))?))
+            }));
+        }
+        work.into_par_iter()
+            .for_each(|(bucket, offers)| bucket.extend(offers));
+    }
+
+    /// Approximate heap memory of the tree: bucket entries plus the per-region
+    /// bucket maps.
+    pub fn memory_bytes(&self) -> usize {
+        let maps: usize = self
+            .regions
+            .iter()
+            .map(|region| {
+                (region.offers.capacity() + region.windows.capacity())
+                    * (std::mem::size_of::<u32>() + std::mem::size_of::<IndexBucket>())
+            })
+            .sum();
+        let buckets: usize = self
+            .buckets()
+            .chain(self.window_buckets())
+            .map(|(_, _, bucket)| bucket.memory_bytes())
+            .sum();
+        self.regions.capacity() * std::mem::size_of::<IndexTreeElement>() + maps + buckets
+    }
+
+    /// Removes the index entries of the given offers.
+    pub fn remove_offers<'a>(&mut self, offers: impl IntoIterator<Item = &'a Offer>) {
+        let mut removed: FxHashMap<(u8, u32), FxHashSet<u32>> = FxHashMap::default();
+        let mut removed_windows: FxHashMap<u8, FxHashSet<u32>> = FxHashMap::default();
+        for offer in offers {
+            let region_id = offer.most_specific_region_id as u8;
+            if offer.flexible {
+                // Bookings may have split the window into intervals of any length.
+                removed_windows
+                    .entry(region_id)
+                    .or_default()
+                    .insert(offer.idx);
+            } else {
+                removed
+                    .entry((region_id, duration_days(offer)))
+                    .or_default()
+                    .insert(offer.idx);
+            }
+        }
+        let touched: FxHashSet<u8> = removed
+            .keys()
+            .map(|&(region_id, _)| region_id)
+            .chain(removed_windows.keys().copied())
+            .collect();
+        for region_id in touched {
+            self.touch_region(region_id);
+        }
+        for ((region_id, days), idxs) in removed {
+            if let Some(bucket) = self.regions[region_id as usize].offers.get_mut(&days) {
+                bucket.retain(|offer| !idxs.contains(&offer.idx));
+            }
+        }
+        for (region_id, idxs) in removed_windows {
+            for bucket in self.regions[region_id as usize].windows.values_mut() {
+                bucket.retain(|window| !idxs.contains(&window.idx));
+            }
+        }
+    }
+
+    /// Points every entry at its offer's new position after the dense store
+    /// was compacted. `new_idx` is indexed by the old position.
+    pub fn remap_offers(&mut self, new_idx: &[u32]) {
+        self.regions
+            .par_iter_mut()
+            .flat_map(|region| {
+                region
+                    .offers
+                    .par_iter_mut()
+                    .chain(region.windows.par_iter_mut())
+            })
+            .for_each(|(_, bucket)| bucket.remap(|idx| new_idx[idx as usize]));
+    }
+
+    /// All non-empty buckets of fixed offers as `(region_id, duration_days, bucket)`.
+    pub(crate) fn buckets(&self) -> impl Iterator<Item = (u8, u32, &IndexBucket)> + '_ {
+        self.non_empty_buckets(|region| &region.offers)
+    }
+
+    /// All non-empty buckets of flexible offers' free intervals.
+    pub(crate) fn window_buckets(&self) -> impl Iterator<Item = (u8, u32, &IndexBucket)> + '_ {
+        self.non_empty_buckets(|region| &region.windows)
+    }
+
+    fn non_empty_buckets(
+        &self,
+        buckets: impl Fn(&IndexTreeElement) -> &FxHashMap<u32, IndexBucket> + Copy + 'static,
+    ) -> impl Iterator<Item = (u8, u32, &IndexBucket)> + '_ {
+        self.regions
+            .iter()
+            .enumerate()
+            .flat_map(move |(region_id, region)| {
+                buckets(region)
+                    .iter()
+                    .map(move |(days, bucket)| (region_id as u8, *days, bucket))
+            })
+            .filter(|(_, _, bucket)| bucket.len() > 0)
+    }
+
+    /// Replaces a bucket with entries that are already sorted by start date.
+    pub(crate) fn set_bucket(
+        &mut self,
+        region_id: u8,
+        days: u32,
+        flexible: bool,
+        offers: Vec<IndexTreeOffer>,
+    ) {
+        self.touch_region(region_id);
+        let region = &mut self.regions[region_id as usize];
+        let buckets = if flexible {
+            &mut region.windows
+        } else {
+            &mut region.offers
+        };
+        buckets.insert(days, IndexBucket::from_sorted(offers));
     }
 
     pub fn clear_offers(&mut self) {
         for element in &mut self.regions {
             element.offers.clear();
+            element.windows.clear();
+            element.subtree_version += 1;
         }
     }
 }
@@ -115,21 +562,14 @@ impl IndexTree {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::json_models::CarType;
+    use crate::test_support;
 
     fn get_offer(start_date: u64, end_date: u64, idx: u32) -> Offer {
         Offer {
             start_date,
             end_date,
-            number_seats: 0,
-            price: 0,
-            car_type: CarType::Small,
-            has_vollkasko: false,
             idx,
-            id: "".to_string(),
-            data: "".to_string(),
-            most_specific_region_id: 0,
-            free_kilometers: 0,
+            ..test_support::blank_offer()
         }
     }
 
@@ -230,11 +670,184 @@ mod tests {
 
         assert_eq!(results, vec![2]); // Offers with same start time
     }
+
+    #[test]
+    fn bulk_insert_matches_single_inserts() {
+        let offers: Vec<Offer> = [(20, 25), (10, 15), (15, 20), (10, 20), (5, 10)]
+            .iter()
+            .enumerate()
+            .map(|(idx, &(start, end))| {
+                let mut offer = get_offer(start, end, idx as u32);
+                offer.most_specific_region_id = 1 + idx as u32 % 2;
+                offer
+            })
+            .collect();
+
+        let mut single = IndexTree::populate_with_regions(&ROOT_REGION);
+        for offer in &offers {
+            single.insert_offer(offer.most_specific_region_id as u8, offer);
+        }
+        let mut bulk = IndexTree::populate_with_regions(&ROOT_REGION);
+        bulk.bulk_insert(&offers);
+
+        let mut expected: Vec<u32> = single.get_available_offers(0, 0, 10, 20).collect();
+        let mut results: Vec<u32> = bulk.get_available_offers(0, 0, 10, 20).collect();
+        expected.sort();
+        results.sort();
+        assert_eq!(results, expected);
+        assert_eq!(results, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn removed_and_remapped_offers() {
+        let mut tree = IndexTree::populate_with_regions(&ROOT_REGION);
+        let offers = [
+            get_offer(10, 15, 0),
+            get_offer(15, 20, 1),
+            get_offer(12, 18, 2),
+        ];
+        for offer in &offers {
+            tree.insert_offer(0, offer);
+        }
+
+        tree.remove_offers([&offers[1]]);
+        let mut results: Vec<u32> = tree.get_available_offers(0, 0, 10, 20).collect();
+        results.sort();
+        assert_eq!(results, vec![0, 2]);
+
+        tree.remap_offers(&[0, u32::MAX, 1]);
+        let mut results: Vec<u32> = tree.get_available_offers(0, 0, 10, 20).collect();
+        results.sort();
+        assert_eq!(results, vec![0, 1]);
+    }
+
+    #[test]
+    fn searches_overlapping_and_excluded_regions_once() {
+        let mut tree = IndexTree::populate_with_regions(&ROOT_REGION);
+        // Mitte (21) and Kreuzberg (22) are in Berlin (7), Maxvorstadt (24)
+        // in Munich (8), all in Germany (1).
+        for (idx, region_id) in [(1, 21), (2, 22), (3, 24), (4, 7)] {
+            tree.insert_offer(region_id, &get_offer(10, 15, idx));
+        }
+        let search = |included: &[u8], excluded: &[u8]| {
+            let mut results: Vec<u32> = tree
+                .get_available_offers_in(tree.resolve_regions(included, excluded), 0, 0, 20)
+                .collect();
+            results.sort_unstable();
+            results
+        };
+
+        assert_eq!(search(&[7, 21, 7], &[]), [1, 2, 4]);
+        assert_eq!(search(&[21, 24], &[]), [1, 3]);
+        assert_eq!(search(&[1], &[7]), [3]);
+        assert_eq!(search(&[1], &[22]), [1, 3, 4]);
+        assert!(search(&[21], &[1]).is_empty());
+    }
+
+    #[test]
+    fn groups_regions_by_direct_sub_region() {
+        let tree = IndexTree::populate_with_regions(&ROOT_REGION);
+        let groups = tree.region_groups(&[1, 21]);
+        assert_eq!(groups[1], 1);
+        assert_eq!(groups[7], 7);
+        // Included regions inside another one keep its grouping.
+        assert_eq!(groups[21], 7);
+        assert_eq!(groups[58], 7);
+        assert_eq!(groups[64], 8);
+        assert_eq!(groups[0], u8::MAX);
+        assert_eq!(tree.region_groups(&[21, 1]), groups);
+    }
+
+    #[test]
+    fn keeps_region_names_and_paths() {
+        let tree = IndexTree::populate_with_regions(&ROOT_REGION);
+        assert_eq!(tree.region_name(7), Some("Berlin"));
+        let path: Vec<_> = tree.region_path(21).unwrap();
+        assert_eq!(
+            path,
+            [
+                (0, "European Union"),
+                (1, "Germany"),
+                (7, "Berlin"),
+                (21, "Mitte")
+            ]
+        );
+        assert_eq!(tree.region_path(0).unwrap().len(), 1);
+        assert_eq!(tree.region_path(200), None);
+    }
+
+    #[test]
+    fn finds_regions_within_a_radius() {
+        let tree = IndexTree::populate_with_regions(&ROOT_REGION);
+        let near_munich_airport = GeoFilter::parse(Some("48.35,11.78"), Some(30.0))
+            .unwrap()
+            .unwrap();
+        let regions: Vec<u8> = tree
+            .resolve_regions(&[0], &[])
+            .into_iter()
+            .filter(|&region_id| tree.region_within(region_id, &near_munich_airport))
+            .collect();
+        let names: FxHashSet<_> = regions
+            .iter()
+            .map(|&region_id| tree.region_name(region_id).unwrap())
+            .collect();
+        // The city centre is ~29 km away; its districts have no coordinates.
+        assert_eq!(names, FxHashSet::from_iter(["Munich", "Munich Airport"]));
+        let near_munich_airport = GeoFilter {
+            radius_km: 10.0,
+            ..near_munich_airport
+        };
+        assert_eq!(
+            tree.resolve_regions(&[0], &[])
+                .into_iter()
+                .filter(|&region_id| tree.region_within(region_id, &near_munich_airport))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn flexible_offers_match_any_fitting_free_interval() {
+        let mut tree = IndexTree::populate_with_regions(&ROOT_REGION);
+        let mut window = get_offer(0, 10 * DAY_MS, 0);
+        window.flexible = true;
+        window.most_specific_region_id = 1;
+        tree.insert_offer(1, &window);
+        tree.insert_offer(0, &get_offer(DAY_MS, 4 * DAY_MS, 1));
+
+        let available = |tree: &IndexTree, days: u32, start: u64, end: u64| -> Vec<u32> {
+            tree.get_available_offers(0, days, start * DAY_MS, end * DAY_MS)
+                .collect()
+        };
+        assert_eq!(available(&tree, 3, 1, 4), vec![1, 0]);
+        assert_eq!(available(&tree, 3, 8, 12), Vec::<u32>::new());
+        assert_eq!(available(&tree, 10, 0, 12), vec![0]);
+
+        // Booking days 2..5 leaves 0..2 and 5..10 free.
+        tree.replace_free_intervals(
+            &window,
+            &[(0, 10 * DAY_MS)],
+            &[(0, 2 * DAY_MS), (5 * DAY_MS, 10 * DAY_MS)],
+        );
+        assert_eq!(available(&tree, 3, 1, 5), vec![1]);
+        assert_eq!(available(&tree, 2, 0, 10), vec![0]);
+        assert_eq!(available(&tree, 5, 0, 10), vec![0]);
+        assert_eq!(available(&tree, 6, 0, 10), Vec::<u32>::new());
+
+        tree.remove_offers([&window]);
+        assert_eq!(available(&tree, 2, 0, 10), Vec::<u32>::new());
+        assert_eq!(tree.window_buckets().count(), 0);
+    }
 }
 
 #[derive(Deserialize, Clone)]
 pub struct Region {
     id: u8,
+    name: String,
+    #[serde(default)]
+    lat: Option<f64>,
+    #[serde(default)]
+    lon: Option<f64>,
     subregions: Vec<Region>,
 }
 
@@ -251,6 +864,8 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 7,
               "name": "Berlin",
+              "lat": 52.52,
+              "lon": 13.405,
               "subregions": [
                 {
                   "id": 21,
@@ -295,6 +910,8 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
                 {
                   "id": 23,
                   "name": "Berlin Brandenburg Airport",
+                  "lat": 52.366,
+                  "lon": 13.503,
                   "subregions": [
                     {
                       "id": 62,
@@ -317,6 +934,8 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 8,
               "name": "Munich",
+              "lat": 48.137,
+              "lon": 11.575,
               "subregions": [
                 {
                   "id": 24,
@@ -401,6 +1020,8 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
                 {
                   "id": 28,
                   "name": "Munich Airport",
+                  "lat": 48.353,
+                  "lon": 11.786,
                   "subregions": [
                     {
                       "id": 72,
@@ -423,6 +1044,8 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 9,
               "name": "Frankfurt",
+              "lat": 50.11,
+              "lon": 8.682,
               "subregions": [
                 {
                   "id": 29,
@@ -447,6 +1070,8 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
                 {
                   "id": 30,
                   "name": "Frankfurt Airport",
+                  "lat": 50.038,
+                  "lon": 8.562,
                   "subregions": [
                     {
                       "id": 76,
@@ -475,10 +1100,14 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 10,
               "name": "Paris",
+              "lat": 48.857,
+              "lon": 2.352,
               "subregions": [
                 {
                   "id": 31,
                   "name": "Charles de Gaulle Airport",
+                  "lat": 49.01,
+                  "lon": 2.548,
                   "subregions": [
                     {
                       "id": 78,
@@ -513,6 +1142,8 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
                 {
                   "id": 32,
                   "name": "Orly Airport",
+                  "lat": 48.723,
+                  "lon": 2.379,
                   "subregions": [
                     {
                       "id": 82,
@@ -595,10 +1226,14 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 11,
               "name": "Nice",
+              "lat": 43.71,
+              "lon": 7.262,
               "subregions": [
                 {
                   "id": 36,
                   "name": "Nice Côte d'Azur Airport",
+                  "lat": 43.658,
+                  "lon": 7.215,
                   "subregions": [
                     {
                       "id": 90,
@@ -647,10 +1282,14 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 12,
               "name": "Rome",
+              "lat": 41.903,
+              "lon": 12.496,
               "subregions": [
                 {
                   "id": 38,
                   "name": "Leonardo da Vinci–Fiumicino Airport",
+                  "lat": 41.8,
+                  "lon": 12.239,
                   "subregions": [
                     {
                       "id": 94,
@@ -693,10 +1332,14 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 13,
               "name": "Milan",
+              "lat": 45.464,
+              "lon": 9.19,
               "subregions": [
                 {
                   "id": 40,
                   "name": "Malpensa Airport",
+                  "lat": 45.63,
+                  "lon": 8.723,
                   "subregions": [
                     {
                       "id": 98,
@@ -717,6 +1360,8 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
                 {
                   "id": 41,
                   "name": "Linate Airport",
+                  "lat": 45.445,
+                  "lon": 9.277,
                   "subregions": [
                     {
                       "id": 100,
@@ -752,10 +1397,14 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 14,
               "name": "Venice",
+              "lat": 45.441,
+              "lon": 12.316,
               "subregions": [
                 {
                   "id": 43,
                   "name": "Venice Marco Polo Airport",
+                  "lat": 45.505,
+                  "lon": 12.352,
                   "subregions": [
                     {
                       "id": 103,
@@ -797,10 +1446,14 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 15,
               "name": "Lisbon",
+              "lat": 38.722,
+              "lon": -9.139,
               "subregions": [
                 {
                   "id": 45,
                   "name": "Lisbon Airport",
+                  "lat": 38.774,
+                  "lon": -9.134,
                   "subregions": [
                     {
                       "id": 106,
@@ -843,10 +1496,14 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 16,
               "name": "Porto",
+              "lat": 41.158,
+              "lon": -8.629,
               "subregions": [
                 {
                   "id": 47,
                   "name": "Porto Airport",
+                  "lat": 41.248,
+                  "lon": -8.681,
                   "subregions": [
                     {
                       "id": 110,
@@ -895,10 +1552,14 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 17,
               "name": "Amsterdam",
+              "lat": 52.368,
+              "lon": 4.904,
               "subregions": [
                 {
                   "id": 49,
                   "name": "Amsterdam Airport Schiphol",
+                  "lat": 52.31,
+                  "lon": 4.768,
                   "subregions": [
                     {
                       "id": 114,
@@ -941,10 +1602,14 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 18,
               "name": "Rotterdam",
+              "lat": 51.924,
+              "lon": 4.478,
               "subregions": [
                 {
                   "id": 51,
                   "name": "Rotterdam The Hague Airport",
+                  "lat": 51.957,
+                  "lon": 4.437,
                   "subregions": [
                     {
                       "id": 118,
@@ -986,10 +1651,14 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 19,
               "name": "Brussels",
+              "lat": 50.85,
+              "lon": 4.352,
               "subregions": [
                 {
                   "id": 53,
                   "name": "Brussels Airport",
+                  "lat": 50.901,
+                  "lon": 4.484,
                   "subregions": [
                     {
                       "id": 121,
@@ -1003,6 +1672,8 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
                 {
                   "id": 54,
                   "name": "Brussels South Charleroi Airport",
+                  "lat": 50.459,
+                  "lon": 4.454,
                   "subregions": [
                     {
                       "id": 122,
@@ -1038,6 +1709,8 @@ pub static ROOT_REGION: Lazy<Region> = Lazy::new(|| {
             {
               "id": 20,
               "name": "Antwerp",
+              "lat": 51.219,
+              "lon": 4.402,
               "subregions": [
                 {
                   "id": 56,
@@ -0,0 +1,733 @@
+use crate::attributes::{AttributeKind, SCHEMA};
+use crate::car_types::CarType;
+use crate::config::CONFIG;
+use crate::db_models::Offer;
+use crate::expiry;
+use fxhash::FxHashMap;
+use serde::Deserialize;
+
+/// One element of the `offers` array of a `POST /api/offers` body.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OfferRecord {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub data: String,
+    #[serde(rename = "mostSpecificRegionID")]
+    pub most_specific_region_id: u64,
+    pub start_date: u64,
+    pub end_date: u64,
+    pub number_seats: u64,
+    pub price: u64,
+    #[serde(default)]
+    pub currency: Option<String>,
+    pub car_type: String,
+    pub has_vollkasko: bool,
+    pub free_kilometers: u64,
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+    #[serde(default)]
+    pub flexible: bool,
+    /// Values of the attributes declared in [`SCHEMA`], by name.
+    #[serde(default)]
+    pub attributes: FxHashMap<String, serde_json::Value>,
+}
+
+impl OfferRecord {
+    /// Validates the record and converts it into a store offer. The `idx` is
+    /// assigned later, when the offer is inserted into the dense store.
+    pub fn into_offer(self) -> Result<Offer, &'static str> {
+        if self.most_specific_region_id > u8::MAX as u64 {
+            return Err("Invalid field 'mostSpecificRegionID'");
+        }
+        if self.end_date < self.start_date {
+            return Err("Field 'endDate' is before 'startDate'");
+        }
+        let car_type = self
+            .car_type
+            .parse::<CarType>()
+            .map_err(|_| "Invalid car type")?;
+        let currency = match &self.currency {
+            Some(code) => code.parse().map_err(|_| "Invalid field 'currency'")?,
+            None => CONFIG.default_currency,
+        };
+        let ttl_seconds = match (self.ttl_seconds, CONFIG.offer_ttl_secs) {
+            (Some(own), Some(global)) => Some(own.min(global)),
+            (own, global) => own.or(global),
+        };
+        let expires_at = ttl_seconds.map_or(u64::MAX, |ttl| {
+            expiry::now_millis().saturating_add(ttl.saturating_mul(1000))
+        });
+
+        Ok(Offer {
+            idx: 0,
+            id: self.id,
+            data: self.data,
+            most_specific_region_id: self.most_specific_region_id as u32,
+            start_date: self.start_date,
+            end_date: self.end_date,
+            number_seats: u32::try_from(self.number_seats)
+                .map_err(|_| "Invalid field 'numberSeats'")?,
+            price: u32::try_from(self.price).map_err(|_| "Invalid field 'price'")?,
+            currency,
+            car_type,
+            has_vollkasko: self.has_vollkasko,
+            free_kilometers: u32::try_from(self.free_kilometers)
+                .map_err(|_| "Invalid field 'freeKilometers'")?,
+            expires_at,
+            flexible: self.flexible,
+            attributes: SCHEMA.encode(&self.attributes)?,
+        })
+    }
+}
+
+/// Parses a single JSON offer object into a validated offer.
+pub fn decode_offer(element: &[u8]) -> Result<Offer, &'static str> {
+    sonic_rs::from_slice::<OfferRecord>(element)
+        .map_err(|_| "Invalid offer")?
+        .into_offer()
+}
+
+/// Incremental decoder for the body formats accepted by `POST /api/offers`.
+/// Every format ends up in [`OfferRecord::into_offer`], so validation is the
+/// same regardless of how the offers were encoded.
+pub enum OfferDecoder {
+    /// `application/json`: a single `{"offers": [...]}` document.
+    Json(OfferArrayScanner),
+    /// `application/x-ndjson`: one offer object per line.
+    Ndjson(LineSplitter),
+    /// `text/csv`: a header row of `Offer` field names, then one offer per row.
+    Csv(CsvDecoder),
+}
+
+impl OfferDecoder {
+    /// Picks the decoder for a `Content-Type` header value. A missing header
+    /// is treated as JSON; unsupported media types yield `None`.
+    pub fn for_content_type(content_type: Option<&str>) -> Option<Self> {
+        let media_type = content_type
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase());
+        match media_type.as_deref() {
+            None | Some("") | Some("application/json") => {
+                Some(OfferDecoder::Json(OfferArrayScanner::default()))
+            }
+            Some("application/x-ndjson") => Some(OfferDecoder::Ndjson(LineSplitter::default())),
+            Some("text/csv") => Some(OfferDecoder::Csv(CsvDecoder::default())),
+            _ => None,
+        }
+    }
+
+    /// Feeds the next body chunk, calling `on_offer` for every offer completed
+    /// by it. Errors are returned for malformed framing only; offers that fail
+    /// validation are passed to `on_offer` as `Err`.
+    pub fn feed(
+        &mut self,
+        chunk: &[u8],
+        mut on_offer: impl FnMut(Result<Offer, &'static str>),
+    ) -> Result<(), &'static str> {
+        match self {
+            OfferDecoder::Json(scanner) => {
+                scanner.feed(chunk, |element| on_offer(decode_offer(element)))
+            }
+            OfferDecoder::Ndjson(lines) => {
+                lines.feed(chunk, |line| {
+                    if !line.trim_ascii().is_empty() {
+                        on_offer(decode_offer(line))
+                    }
+                });
+                Ok(())
+            }
+            OfferDecoder::Csv(csv) => csv.feed(chunk, on_offer),
+        }
+    }
+
+    /// Flushes any trailing record and checks that the body was complete.
+    pub fn finish(
+        &mut self,
+        mut on_offer: impl FnMut(Result<Offer, &'static str>),
+    ) -> Result<(), &'static str> {
+        match self {
+            OfferDecoder::Json(scanner) => scanner.finish(),
+            OfferDecoder::Ndjson(lines) => {
+                lines.finish(|line| {
+                    if !line.trim_ascii().is_empty() {
+                        on_offer(decode_offer(line))
+                    }
+                });
+                Ok(())
+            }
+            OfferDecoder::Csv(csv) => csv.finish(on_offer),
+        }
+    }
+}
+
+/// Splits a byte stream into `\n`-terminated lines across chunk boundaries,
+/// stripping a trailing `\r`. With `quoted` set, newlines inside
+/// double-quoted CSV fields do not end a line.
+#[derive(Default)]
+pub struct LineSplitter {
+    buf: Vec<u8>,
+    pos: usize,
+    quoted: bool,
+    in_quotes: bool,
+}
+
+impl LineSplitter {
+    fn quoted() -> Self {
+        Self {
+            quoted: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8], mut on_line: impl FnMut(&[u8])) {
+        self.buf.extend_from_slice(chunk);
+        let mut line_start = 0;
+        while self.pos < self.buf.len() {
+            match self.buf[self.pos] {
+                b'"' if self.quoted => self.in_quotes = !self.in_quotes,
+                b'\n' if !self.in_quotes => {
+                    let line = &self.buf[line_start..self.pos];
+                    on_line(line.strip_suffix(b"\r").unwrap_or(line));
+                    line_start = self.pos + 1;
+                }
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        self.buf.drain(..line_start);
+        self.pos -= line_start;
+    }
+
+    pub fn finish(&mut self, mut on_line: impl FnMut(&[u8])) {
+        if !self.buf.is_empty() {
+            let line = std::mem::take(&mut self.buf);
+            on_line(line.strip_suffix(b"\r").unwrap_or(&line));
+        }
+        self.pos = 0;
+    }
+}
+
+/// Required column names of the CSV header row, in [`OfferRecord`] field order.
+const CSV_COLUMNS: [&str; 10] = [
+    "ID",
+    "data",
+    "mostSpecificRegionID",
+    "startDate",
+    "endDate",
+    "numberSeats",
+    "price",
+    "carType",
+    "hasVollkasko",
+    "freeKilometers",
+];
+
+/// Optional column holding [`OfferRecord::ttl_seconds`].
+const CSV_TTL_COLUMN: &str = "ttlSeconds";
+/// Optional column holding [`OfferRecord::flexible`].
+const CSV_FLEXIBLE_COLUMN: &str = "flexible";
+/// Optional column holding [`OfferRecord::currency`].
+const CSV_CURRENCY_COLUMN: &str = "currency";
+/// Prefix of optional columns holding [`OfferRecord::attributes`], e.g. `attr.fuel`.
+const CSV_ATTRIBUTE_PREFIX: &str = "attr.";
+
+pub struct CsvDecoder {
+    lines: LineSplitter,
+    /// Position of each of [`CSV_COLUMNS`] in a row, known once the header was read.
+    columns: Option<CsvColumns>,
+}
+
+struct CsvColumns {
+    required: [usize; CSV_COLUMNS.len()],
+    ttl: Option<usize>,
+    flexible: Option<usize>,
+    currency: Option<usize>,
+    /// Position and schema slot of each `attr.*` column.
+    attributes: Vec<(usize, usize)>,
+}
+
+impl Default for CsvDecoder {
+    fn default() -> Self {
+        Self {
+            lines: LineSplitter::quoted(),
+            columns: None,
+        }
+    }
+}
+
+impl CsvDecoder {
+    fn feed(
+        &mut self,
+        chunk: &[u8],
+        mut on_offer: impl FnMut(Result<Offer, &'static str>),
+    ) -> Result<(), &'static str> {
+        let mut result = Ok(());
+        let columns = &mut self.columns;
+        self.lines.feed(chunk, |line| {
+            if result.is_ok() {
+                result = Self::handle_line(columns, line, &mut on_offer);
+            }
+        });
+        result
+    }
+
+    fn finish(
+        &mut self,
+        mut on_offer: impl FnMut(Result<Offer, &'static str>),
+    ) -> Result<(), &'static str> {
+        let mut result = Ok(());
+        let columns = &mut self.columns;
+        self.lines.finish(|line| {
+            result = Self::handle_line(columns, line, &mut on_offer);
+        });
+        result?;
+        match self.columns {
+            Some(_) => Ok(()),
+            None => Err("Missing CSV header row"),
+        }
+    }
+
+    fn handle_line(
+        columns: &mut Option<CsvColumns>,
+        line: &[u8],
+        on_offer: &mut impl FnMut(Result<Offer, &'static str>),
+    ) -> Result<(), &'static str> {
+        if line.trim_ascii().is_empty() {
+            return Ok(());
+        }
+        let fields = split_csv_row(line).ok_or("Invalid CSV row")?;
+        match columns {
+            None => {
+                let mut positions = [0; CSV_COLUMNS.len()];
+                for (position, name) in positions.iter_mut().zip(CSV_COLUMNS) {
+                    *position = fields
+                        .iter()
+                        .position(|field| field.trim() == name)
+                        .ok_or("CSV header is missing an offer field")?;
+                }
+                *columns = Some(CsvColumns {
+                    required: positions,
+                    ttl: fields
+                        .iter()
+                        .position(|field| field.trim() == CSV_TTL_COLUMN),
+                    flexible: fields
+                        .iter()
+                        .position(|field| field.trim() == CSV_FLEXIBLE_COLUMN),
+                    currency: fields
+                        .iter()
+                        .position(|field| field.trim() == CSV_CURRENCY_COLUMN),
+                    attributes: fields
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(position, field)| {
+                            let name = field.trim().strip_prefix(CSV_ATTRIBUTE_PREFIX)?;
+                            Some(
+                                SCHEMA
+                                    .position(name)
+                                    .map(|slot| (position, slot))
+                                    .ok_or("CSV header names an unknown attribute"),
+                            )
+                        })
+                        .collect::<Result<_, _>>()?,
+                });
+            }
+            Some(columns) => {
+                on_offer(csv_record(&fields, columns).and_then(OfferRecord::into_offer))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits one CSV row into its fields, unquoting `"..."` fields and `""` escapes.
+fn split_csv_row(line: &[u8]) -> Option<Vec<String>> {
+    let line = std::str::from_utf8(line).ok()?;
+    let mut fields = Vec::with_capacity(CSV_COLUMNS.len());
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut in_quotes = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    Some(fields)
+}
+
+fn csv_record(fields: &[String], columns: &CsvColumns) -> Result<OfferRecord, &'static str> {
+    let field = |column: usize| -> Result<&str, &'static str> {
+        fields
+            .get(columns.required[column])
+            .map(|field| field.trim())
+            .ok_or("Missing CSV field")
+    };
+    let ttl_seconds = match columns.ttl.and_then(|position| fields.get(position)) {
+        Some(ttl) if !ttl.trim().is_empty() => Some(
+            ttl.trim()
+                .parse()
+                .map_err(|_| "Invalid numeric CSV field")?,
+        ),
+        _ => None,
+    };
+    let flexible = match columns.flexible.and_then(|position| fields.get(position)) {
+        Some(flexible) if !flexible.trim().is_empty() => flexible
+            .trim()
+            .parse()
+            .map_err(|_| "Invalid boolean CSV field")?,
+        _ => false,
+    };
+    let currency = columns
+        .currency
+        .and_then(|position| fields.get(position))
+        .map(|currency| currency.trim())
+        .filter(|currency| !currency.is_empty())
+        .map(str::to_string);
+    let mut attributes = FxHashMap::default();
+    for &(position, slot) in &columns.attributes {
+        let value = match fields.get(position).map(|field| field.trim()) {
+            Some(value) if !value.is_empty() => value,
+            _ => continue,
+        };
+        let attribute = &SCHEMA.attributes[slot];
+        let value = match attribute.kind {
+            AttributeKind::Categorical { .. } => serde_json::Value::from(value),
+            AttributeKind::Numeric => serde_json::Value::from(
+                value
+                    .parse::<u64>()
+                    .map_err(|_| "Invalid numeric CSV field")?,
+            ),
+            AttributeKind::Boolean => serde_json::Value::from(
+                value
+                    .parse::<bool>()
+                    .map_err(|_| "Invalid boolean CSV field")?,
+            ),
+        };
+        attributes.insert(attribute.name.clone(), value);
+    }
+    let number = |column: usize| -> Result<u64, &'static str> {
+        field(column)?
+            .parse()
+            .map_err(|_| "Invalid numeric CSV field")
+    };
+
+    Ok(OfferRecord {
+        id: field(0)?.to_string(),
+        data: field(1)?.to_string(),
+        most_specific_region_id: number(2)?,
+        start_date: number(3)?,
+        end_date: number(4)?,
+        number_seats: number(5)?,
+        price: number(6)?,
+        currency,
+        car_type: field(7)?.to_string(),
+        has_vollkasko: field(8)?.parse().map_err(|_| "Invalid boolean CSV field")?,
+        free_kilometers: number(9)?,
+        ttl_seconds,
+        flexible,
+        attributes,
+    })
+}
+
+#[derive(Default, Debug, PartialEq, Eq)]
+enum ScanState {
+    /// Still looking for the `"offers": [` member of the top-level object.
+    #[default]
+    Preamble,
+    InArray,
+    Done,
+}
+
+/// Splits a `{"offers": [...]}` document into its array elements while the
+/// body is still arriving. Only the bytes of the element currently being
+/// received are buffered, so memory stays bounded by the largest offer rather
+/// than by the size of the request.
+#[derive(Default)]
+pub struct OfferArrayScanner {
+    buf: Vec<u8>,
+    pos: usize,
+    depth: u32,
+    in_string: bool,
+    escaped: bool,
+    state: ScanState,
+    /// Start of the string (preamble) or array element (in array) being scanned.
+    token_start: Option<usize>,
+    last_string_is_offers: bool,
+    value_is_offers: bool,
+}
+
+impl OfferArrayScanner {
+    /// Feeds the next body chunk, calling `on_element` with the raw bytes of
+    /// every array element completed by it.
+    pub fn feed(
+        &mut self,
+        chunk: &[u8],
+        mut on_element: impl FnMut(&[u8]),
+    ) -> Result<(), &'static str> {
+        if self.state == ScanState::Done {
+            return Ok(());
+        }
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.extend_from_slice(chunk);
+
+        while self.pos < buf.len() && self.state != ScanState::Done {
+            let c = buf[self.pos];
+
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if c == b'\\' {
+                    self.escaped = true;
+                } else if c == b'"' {
+                    self.in_string = false;
+                    if self.state == ScanState::Preamble && self.depth == 1 {
+                        let start = self.token_start.take().unwrap_or(self.pos);
+                        self.last_string_is_offers = &buf[start..self.pos] == b"offers";
+                    }
+                }
+                self.pos += 1;
+                continue;
+            }
+
+            match self.state {
+                ScanState::Preamble => match c {
+                    b'"' => {
+                        self.in_string = true;
+                        if self.depth == 1 {
+                            self.value_is_offers = false;
+                            self.token_start = Some(self.pos + 1);
+                        }
+                    }
+                    b':' if self.depth == 1 => self.value_is_offers = self.last_string_is_offers,
+                    b',' if self.depth == 1 => self.value_is_offers = false,
+                    b'[' if self.depth == 1 && self.value_is_offers => {
+                        self.depth += 1;
+                        self.state = ScanState::InArray;
+                    }
+                    b'{' | b'[' => {
+                        self.value_is_offers = false;
+                        self.depth += 1;
+                    }
+                    b'}' | b']' => {
+                        if self.depth == 0 {
+                            return Err("Unbalanced JSON document");
+                        }
+                        self.depth -= 1;
+                        if self.depth == 0 {
+                            return Err("Missing field 'offers'");
+                        }
+                    }
+                    _ => {}
+                },
+                ScanState::InArray => {
+                    let at_array_level = self.depth == 2;
+                    match (self.token_start, c) {
+                        (Some(start), b',' | b']') if at_array_level => {
+                            on_element(buf[start..self.pos].trim_ascii_end());
+                            self.token_start = None;
+                            if c == b']' {
+                                self.depth -= 1;
+                                self.state = ScanState::Done;
+                            }
+                        }
+                        (None, b']') if at_array_level => {
+                            self.depth -= 1;
+                            self.state = ScanState::Done;
+                        }
+                        (None, b',') if at_array_level => {}
+                        (None, c) if at_array_level && c.is_ascii_whitespace() => {}
+                        (token_start, c) => {
+                            if token_start.is_none() {
+                                self.token_start = Some(self.pos);
+                            }
+                            match c {
+                                b'"' => self.in_string = true,
+                                b'{' | b'[' => self.depth += 1,
+                                // `]` at the array level ends it above.
+                                b'}' | b']' if self.depth <= 2 => {
+                                    return Err("Unbalanced JSON document");
+                                }
+                                b'}' | b']' => self.depth -= 1,
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                ScanState::Done => unreachable!(),
+            }
+            self.pos += 1;
+        }
+
+        // Drop everything that no pending token refers to anymore.
+        let keep_from = self.token_start.unwrap_or(self.pos);
+        buf.drain(..keep_from);
+        self.pos -= keep_from;
+        self.token_start = self.token_start.map(|_| 0);
+        self.buf = buf;
+        Ok(())
+    }
+
+    /// Checks that the body contained a complete `offers` array.
+    pub fn finish(&self) -> Result<(), &'static str> {
+        match self.state {
+            ScanState::Done => Ok(()),
+            ScanState::InArray => Err("Unexpected end of body"),
+            ScanState::Preamble => Err("Missing field 'offers'"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_models::SAMPLE_POST_REQUEST;
+
+    fn scan_in_chunks(body: &[u8], chunk_size: usize) -> Result<Vec<String>, &'static str> {
+        let mut scanner = OfferArrayScanner::default();
+        let mut elements = Vec::new();
+        for chunk in body.chunks(chunk_size) {
+            scanner.feed(chunk, |element| {
+                elements.push(String::from_utf8(element.to_vec()).unwrap())
+            })?;
+        }
+        scanner.finish()?;
+        Ok(elements)
+    }
+
+    #[test]
+    fn splits_elements_regardless_of_chunk_boundaries() {
+        let body = br#"{"other": {"offers": [1]}, "offers": [ {"a": "x,]}"}, {"b": [1, 2]} ,3 ] }"#;
+        for chunk_size in 1..body.len() {
+            assert_eq!(
+                scan_in_chunks(body, chunk_size).unwrap(),
+                vec![r#"{"a": "x,]}"}"#, r#"{"b": [1, 2]}"#, "3"],
+                "chunk size {}",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn handles_escaped_quotes_in_strings() {
+        let body = br#"{"offers":[{"data":"a\"],"},{"data":"\\"}]}"#;
+        assert_eq!(
+            scan_in_chunks(body, 3).unwrap(),
+            vec![r#"{"data":"a\"],"}"#, r#"{"data":"\\"}"#]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_or_missing_array() {
+        assert!(scan_in_chunks(br#"{"offers":[{"a":1},"#, 4).is_err());
+        assert!(scan_in_chunks(br#"{"offer":[]}"#, 4).is_err());
+        assert!(scan_in_chunks(br#"{"offers":[]}"#, 4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unbalanced_arrays() {
+        for body in [
+            &br#"{"offers":[}}}"#[..],
+            br#"{"offers":[{"a":1}}]}"#,
+            br#"{"offers":[{"a":[1]]}]}"#,
+        ] {
+            assert_eq!(
+                scan_in_chunks(body, 2),
+                Err("Unbalanced JSON document"),
+                "{}",
+                String::from_utf8_lossy(body)
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_sample_offer() {
+        let elements = scan_in_chunks(SAMPLE_POST_REQUEST.as_bytes(), 7).unwrap();
+        assert_eq!(elements.len(), 1);
+        let offer = decode_offer(elements[0].as_bytes()).unwrap();
+        assert_eq!(offer.id, "01934a57-7988-7879-bb9b-e03bd4e77b9d");
+        assert_eq!(offer.most_specific_region_id, 5);
+        assert_eq!(offer.car_type, "luxury".parse::<CarType>().unwrap());
+        assert_eq!(offer.free_kilometers, 120);
+    }
+
+    fn decode_in_chunks(
+        content_type: &str,
+        body: &[u8],
+        chunk_size: usize,
+    ) -> Result<Vec<Result<Offer, &'static str>>, &'static str> {
+        let mut decoder = OfferDecoder::for_content_type(Some(content_type)).unwrap();
+        let mut offers = Vec::new();
+        for chunk in body.chunks(chunk_size) {
+            decoder.feed(chunk, |offer| offers.push(offer))?;
+        }
+        decoder.finish(|offer| offers.push(offer))?;
+        Ok(offers)
+    }
+
+    #[test]
+    fn decodes_ndjson() {
+        let body = concat!(
+            r#"{"ID":"a","data":"x","mostSpecificRegionID":5,"startDate":1,"endDate":2,"numberSeats":5,"price":100,"carType":"small","hasVollkasko":true,"freeKilometers":10}"#,
+            "\r\n\n",
+            r#"{"ID":"b"}"#,
+            "\n",
+            r#"{"ID":"c","data":"y","mostSpecificRegionID":6,"startDate":1,"endDate":2,"numberSeats":4,"price":200,"carType":"family","hasVollkasko":false,"freeKilometers":20}"#,
+        );
+        for chunk_size in [1, 5, body.len()] {
+            let offers =
+                decode_in_chunks("application/x-ndjson", body.as_bytes(), chunk_size).unwrap();
+            assert_eq!(offers.len(), 3);
+            assert_eq!(offers[0].as_ref().unwrap().id, "a");
+            assert!(offers[1].is_err());
+            assert_eq!(
+                offers[2].as_ref().unwrap().car_type,
+                "family".parse::<CarType>().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_csv_with_reordered_columns() {
+        let body = "price,ID,data,mostSpecificRegionID,startDate,endDate,numberSeats,carType,hasVollkasko,freeKilometers,ttlSeconds\r\n\
+                    100,a,\"x,\"\"y\"\"\",5,1,2,5,small,true,10,60\n\
+                    abc,b,x,5,1,2,5,small,true,10,\n\
+                    300,c,z,7,1,2,4,sports,false,30,";
+        for chunk_size in [1, 7, body.len()] {
+            let offers =
+                decode_in_chunks("text/csv; charset=utf-8", body.as_bytes(), chunk_size).unwrap();
+            assert_eq!(offers.len(), 3);
+            let first = offers[0].as_ref().unwrap();
+            assert_eq!(
+                (first.id.as_str(), first.data.as_str(), first.price),
+                ("a", "x,\"y\"", 100)
+            );
+            assert!(first.expires_at <= expiry::now_millis() + 60_000);
+            assert!(offers[1].is_err());
+            let last = offers[2].as_ref().unwrap();
+            assert_eq!(last.car_type, "sports".parse::<CarType>().unwrap());
+            assert_eq!(last.expires_at, u64::MAX);
+        }
+    }
+
+    #[test]
+    fn rejects_csv_without_offer_header() {
+        assert!(decode_in_chunks("text/csv", b"ID,data\na,b\n", 4).is_err());
+        assert!(decode_in_chunks("text/csv", b"", 4).is_err());
+        assert!(OfferDecoder::for_content_type(Some("application/xml")).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_offers() {
+        assert!(decode_offer(br#"{"ID":"a"}"#).is_err());
+        let element = scan_in_chunks(SAMPLE_POST_REQUEST.as_bytes(), 64)
+            .unwrap()
+            .remove(0);
+        assert!(decode_offer(element.replace("luxury", "van").as_bytes()).is_err());
+    }
+}
@@ -1,10 +1,16 @@
-use crate::db_models;
+use crate::car_types::CarType;
+use crate::currency::Currency;
 use sonic_rs::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct RequestOffer {
+    /// Regions whose subtrees are searched; overlapping subtrees count once.
     #[serde(rename = "regionID")]
-    pub region_id: u8,
+    pub region_ids: Vec<u8>,
+    /// Regions whose subtrees are left out of the search.
+    #[serde(default, rename = "excludeRegionID")]
+    pub excluded_region_ids: Vec<u8>,
     pub time_range_start: u64,
     pub time_range_end: u64,
     pub number_days: u32,
@@ -19,40 +25,37 @@ pub struct RequestOffer {
     pub car_type: Option<CarType>,
     pub only_vollkasko: Option<bool>,
     pub min_free_kilometer: Option<u32>,
+    /// Currency that price filters, sorting and price ranges are expressed in.
+    /// Without it, prices are compared as stored regardless of currency.
+    pub currency: Option<Currency>,
+    /// `attr.*` parameters without their prefix, see
+    /// [`crate::attributes::AttributeSchema::compile_query`].
+    #[serde(default)]
+    pub attribute_filters: Vec<(String, String)>,
+    /// Bucket spec replacing `priceRangeWidth`, see [`crate::range_facets::RangeFacet`].
+    #[serde(default)]
+    pub price_buckets: Option<String>,
+    /// Bucket spec replacing `minFreeKilometerWidth`.
+    #[serde(default)]
+    pub free_kilometer_buckets: Option<String>,
+    /// Fields to compute summary statistics for, see
+    /// [`crate::summary_stats::StatsRequest`].
+    #[serde(default)]
+    pub stats: Option<String>,
+    /// Facets to compute, see [`crate::facets::FacetSelection`].
+    #[serde(default)]
+    pub facets: Option<String>,
+    /// Adds each offer's most specific region and its name to the results.
+    #[serde(default)]
+    pub include_region: bool,
+    /// `<lat>,<lon>` to restrict the search to regions within `radius_km`,
+    /// see [`crate::geo::GeoFilter`].
+    #[serde(default)]
+    pub near: Option<String>,
+    #[serde(default)]
+    pub radius_km: Option<f64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
-#[serde(rename_all = "lowercase")]
-pub enum CarType {
-    Small,
-    Sports,
-    Luxury,
-    Family,
-}
-
-// impl From<CarType> for db_models::CarType {
-//     fn from(car_type: CarType) -> Self {
-//         match car_type {
-//             CarType::Small => db_models::CarType::Small,
-//             CarType::Sports => db_models::CarType::Sports,
-//             CarType::Luxury => db_models::CarType::Luxury,
-//             CarType::Family => db_models::CarType::Family,
-//         }
-//     }
-// }
-
-// impl PartialEq<CarType> for CarType {
-//     fn eq(&self, other: &CarType) -> bool {
-//         match (self, other) {
-//             (CarType::Small, CarType::Small) => true,
-//             (CarType::Sports, CarType::Sports) => true,
-//             (CarType::Luxury, CarType::Luxury) => true,
-//             (CarType::Family, CarType::Family) => true,
-//             (_, _) => false,
-//         }
-//     }
-// }
-
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub enum SortOrder {
@@ -64,17 +67,198 @@ pub enum SortOrder {
 #[serde(rename_all = "camelCase")]
 pub struct GetReponseBodyModel {
     pub offers: Vec<ResponseOffer>,
+    /// Number of offers matching all filters, across all pages.
+    pub total_count: u32,
+    pub total_pages: u32,
     pub price_ranges: Vec<PriceRange>,
-    pub car_type_counts: CarTypeCount,
+    /// Offers per configured car type, keyed by name.
+    pub car_type_counts: BTreeMap<String, u32>,
     pub seats_count: Vec<SeatCount>,
     pub free_kilometer_range: Vec<FreeKilometerRange>,
     pub vollkasko_count: VollKaskoCount,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attribute_counts: Vec<AttributeFacet>,
+    /// Offers per direct sub-region of the searched regions, see
+    /// [`crate::index_tree::IndexTree::region_groups`].
+    #[serde(default)]
+    pub region_counts: Vec<RegionCount>,
+    /// Summary statistics requested with `stats=`, keyed by field.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stats: BTreeMap<String, SummaryStats>,
+}
+
+/// Counts of one schema attribute: per value for categorical and boolean
+/// attributes, per range for numeric ones.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AttributeFacet {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<AttributeValueCount>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ranges: Vec<AttributeRange>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AttributeValueCount {
+    pub value: String,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AttributeRange {
+    pub start: u32,
+    pub end: u32,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PostResponseBodyModel {
+    pub accepted: u32,
+    pub rejected: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsResponseModel {
+    pub eviction_runs: u64,
+    pub evicted_expired: u64,
+    pub evicted_started: u64,
+    pub compactions: u64,
+    pub reclaimed_slots: u64,
+    pub query_cache_hits: u64,
+    pub query_cache_misses: u64,
+    /// Share of cached searches answered from the cache, 0 before the first.
+    pub query_cache_hit_rate: f64,
+    pub summarized_searches: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsResponseModel {
+    pub offers: u64,
+    pub removed_offers: u64,
+    /// `Offer` slots reserved by the dense store, excluding string contents.
+    pub store_bytes: u64,
+    /// Heap memory of the offers' `id` and `data` strings.
+    pub string_bytes: u64,
+    pub index_bytes: u64,
+    /// Secondary bitmap indexes over the offers, see
+    /// [`crate::secondary_index::SecondaryIndexes`].
+    pub bitmap_bytes: u64,
+    pub total_bytes: u64,
+    pub memory_limit_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HoldRequestModel {
+    pub ttl_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HoldResponseModel {
+    #[serde(rename = "ID")]
+    pub id: String,
+    /// Unix ms at which the offer shows up in searches again.
+    pub held_until: u64,
+    /// Has to be passed along when booking the offer while it is held.
+    pub hold_token: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BookingRequestModel {
+    pub start_date: u64,
+    pub end_date: u64,
+    /// The token of the hold on the offer, if the client holds it.
+    pub hold_token: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BookingResponseModel {
+    #[serde(rename = "ID")]
+    pub id: String,
+    /// What is left of the offer after the booking; empty once it is used up.
+    pub free_intervals: Vec<AvailabilityInterval>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailabilityInterval {
+    pub start_date: u64,
+    pub end_date: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RatesResponseModel {
+    pub currencies: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CompactionResponseModel {
+    pub reclaimed_slots: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
 pub struct ResponseOffer {
     pub ID: String,
     pub data: String, // encoded as base64
+    /// Only set when the search asked for `includeRegion=true`.
+    #[serde(
+        default,
+        rename = "mostSpecificRegionID",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub most_specific_region_id: Option<u32>,
+    #[serde(
+        default,
+        rename = "regionName",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub region_name: Option<String>,
+}
+
+/// Response of `GET /api/regions/{id}/path`, starting at the root region.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RegionPathResponseModel {
+    pub path: Vec<RegionModel>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RegionModel {
+    #[serde(rename = "regionID")]
+    pub region_id: u8,
+    pub name: String,
+}
+
+/// Response of `GET /api/offers/count`. Facets are only present if selected
+/// with `facets=`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CountResponseModel {
+    pub count: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub price_ranges: Vec<PriceRange>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub car_type_counts: BTreeMap<String, u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub seats_count: Vec<SeatCount>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub free_kilometer_range: Vec<FreeKilometerRange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vollkasko_count: Option<VollKaskoCount>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attribute_counts: Vec<AttributeFacet>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub region_counts: Vec<RegionCount>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stats: BTreeMap<String, SummaryStats>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -85,11 +269,10 @@ pub struct PriceRange {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct CarTypeCount {
-    pub small: u32,
-    pub sports: u32,
-    pub luxury: u32,
-    pub family: u32,
+pub struct RegionCount {
+    #[serde(rename = "regionID")]
+    pub region_id: u8,
+    pub count: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -106,71 +289,33 @@ pub struct FreeKilometerRange {
     pub count: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct VollKaskoCount {
     pub true_count: u32,
     pub false_count: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct Offer<'a> {
-    #[serde(rename = "ID")]
-    pub id: &'a str,
-    // TODO: optimize?
-    pub data: String, // base64 encoded 256 Byte array
-    pub most_specific_region_ID: u32,
-    pub start_date: u64,
-    pub end_date: u64,
-    pub number_seats: u32,
-    pub price: u32,
-    pub car_type: CarType,
-    pub has_vollkasko: bool,
-    pub free_kilometers: u32,
-}
-
-pub const SAMPLE_GET_RESPONSE: &str = r#"
-{
-  "offers": [
-    {
-      "ID": "01934a57-7988-7879-bb9b-e03bd4e77b9d",
-      "data": "string"
-    }
-  ],
-  "priceRanges": [
-    {
-      "start": 10000,
-      "end": 15000,
-      "count": 4
-    }
-  ],
-  "carTypeCounts": {
-    "small": 1,
-    "sports": 2,
-    "luxury": 1,
-    "family": 0
-  },
-  "seatsCount": [
-    {
-      "numberSeats": 5,
-      "count": 4
-    }
-  ],
-  "freeKilometerRange": [
-    {
-      "start": 100,
-      "end": 150,
-      "count": 4
-    }
-  ],
-  "vollkaskoCount": {
-    "trueCount": 3,
-    "falseCount": 1
-  }
+/// Statistics of one field over the offers of a search. The values are
+/// absent when no offer matched.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct SummaryStats {
+    pub count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub average: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub median: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p10: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p90: Option<u32>,
 }
-"#;
 
+#[cfg(test)]
 pub const SAMPLE_POST_REQUEST: &str = r#"
 {
   "offers": [
@@ -0,0 +1,25 @@
+pub mod attributes;
+mod block_summary;
+pub mod car_types;
+pub mod config;
+pub mod currency;
+pub mod db_manager;
+pub mod db_models;
+pub mod expiry;
+pub mod facets;
+pub mod geo;
+mod index_bucket;
+pub mod index_tree;
+pub mod ingest;
+pub mod json_models;
+pub mod metrics;
+pub mod parsing;
+pub mod query_cache;
+pub mod range_facets;
+pub mod secondary_index;
+pub mod snapshot;
+pub mod summary_stats;
+#[cfg(test)]
+mod test_support;
+
+pub type GenericError = Box<dyn std::error::Error + Send + Sync>;
@@ -1,166 +1,250 @@
 // #![deny(warnings)]
 
-mod db_manager;
-mod db_models;
-mod json_models;
-mod index_tree;
-mod parsing;
-
-use json_models::*;
-
-use crate::db_manager::DBManager;
-use crate::index_tree::{IndexTree, ROOT_REGION};
 use bytes::Bytes;
-use futures::{StreamExt, TryStreamExt};
-use http_body_util::{BodyExt, Full};
+use clueless::attributes;
+use clueless::car_types::CAR_TYPES;
+use clueless::config::CONFIG;
+use clueless::currency::{Currency, ExchangeRates};
+use clueless::db_manager::{BookingError, DBManager, HoldError};
+use clueless::facets::FacetSelection;
+use clueless::geo::GeoFilter;
+use clueless::index_tree::{IndexTree, ROOT_REGION};
+use clueless::ingest::OfferDecoder;
+use clueless::json_models::{
+    AvailabilityInterval, BookingRequestModel, BookingResponseModel, CompactionResponseModel,
+    HoldRequestModel, HoldResponseModel, PostResponseBodyModel, RatesResponseModel, RegionModel,
+    RegionPathResponseModel, RequestOffer,
+};
+use clueless::metrics::METRICS;
+use clueless::range_facets::RangeFacet;
+use clueless::summary_stats::StatsRequest;
+use clueless::{db_models, expiry, parsing, snapshot, GenericError};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
 use hyper::body::Incoming;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{body::Incoming as IncomingBody, header, Method, Request, Response, StatusCode};
 use hyper_util::rt::TokioIo;
-use sonic_rs::{get_from_bytes_unchecked, to_array_iter_unchecked, JsonValueTrait};
+use once_cell::sync::Lazy;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 
-type GenericError = Box<dyn std::error::Error + Send + Sync>;
 type Result<T> = std::result::Result<T, GenericError>;
 type BoxBody = http_body_util::combinators::BoxBody<Bytes, hyper::Error>;
 
 static INTERNAL_SERVER_ERROR: &[u8] = b"Internal Server Error";
-static OFFER_CREATED: &[u8] = b"Offers were created";
 static NOTFOUND: &[u8] = b"Not Found";
 static OFFERS_CLEANED_UP: &[u8] = b"Offers were cleaned up";
+static BAD_REQUEST: &[u8] = b"Bad Request";
+static OFFER_ALREADY_HELD: &[u8] = b"Offer is already held";
+static NO_RATES_FILE: &[u8] = b"No exchange rates file configured";
+static PAYLOAD_TOO_LARGE: &[u8] = b"Payload Too Large";
+static OFFER_UNAVAILABLE: &[u8] = b"Offer is not available for that time range";
 
 async fn api_post_response(
     req: Request<Incoming>,
     manager: &DBManager,
 ) -> Result<Response<BoxBody>> {
-    let body = req.collect().await?.to_bytes();
-
-    let root_value = unsafe { get_from_bytes_unchecked(&body, &["offers"]).unwrap() };
-
-    let iter = unsafe { to_array_iter_unchecked(root_value.as_raw_str()) };
-
-    // let mut insert = manager.client.insert("offers")?;
+    let max_body_bytes = CONFIG.max_body_bytes;
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > max_body_bytes) {
+        return post_summary_response(StatusCode::PAYLOAD_TOO_LARGE, &Default::default());
+    }
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let Some(mut decoder) = OfferDecoder::for_content_type(content_type) else {
+        return post_summary_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, &Default::default());
+    };
+    if !manager.has_capacity_for(&[]).await {
+        return post_summary_response(StatusCode::INSUFFICIENT_STORAGE, &Default::default());
+    }
 
     if cfg!(debug_assertions) {
         println!("Inserting offers");
     }
 
-    let mut dense_store = manager.dense_store_lock.write().await;
-    let mut region_tree = manager.index_tree_lock.write().await;
-
-    // let data_store = manager.dense_store_lock.get_mut().awa;
-    for elem in iter {
-        match elem {
-            Ok(json_value) => {
-                let id = json_value
-                    .get("ID")
-                    .ok_or("Missing or invalid field 'id'")?
-                    .as_str()
-                    .unwrap()
-                    .to_string();
-                let data = json_value
-                    .get("data")
-                    .ok_or("Missing or invalid field 'data'")?
-                    .as_str()
-                    .unwrap()
-                    .to_string();
-                let most_specific_region_id = json_value
-                    .get("mostSpecificRegionID")
-                    .and_then(|v| v.as_u64())
-                    .ok_or("Missing or invalid field 'mostSpecificRegionID'")?
-                    as u32;
-                let start_date = json_value
-                    .get("startDate")
-                    .and_then(|v| v.as_u64())
-                    .ok_or("Missing or invalid field 'start_date'")?;
-                let end_date = json_value
-                    .get("endDate")
-                    .and_then(|v| v.as_u64())
-                    .ok_or("Missing or invalid field 'endDate'")?;
-                let number_seats = json_value
-                    .get("numberSeats")
-                    .and_then(|v| v.as_u64())
-                    .ok_or("Missing or invalid field 'numberSeats'")?
-                    as u32;
-                let price = json_value
-                    .get("price")
-                    .and_then(|v| v.as_u64())
-                    .ok_or("Missing or invalid field 'price'")? as u32;
-                let car_type = json_value
-                    .get("carType")
-                    .ok_or("Missing or invalid field 'carType'")?
-                    .as_str()
-                    .unwrap()
-                    .to_string();
-                let has_vollkasko = json_value
-                    .get("hasVollkasko")
-                    .and_then(|v| v.as_bool())
-                    .ok_or("Missing or invalid field 'hasVollkasko'")?;
-                let free_kilometers = json_value
-                    .get("freeKilometers")
-                    .and_then(|v| v.as_u64())
-                    .ok_or("Missing or invalid field 'freeKilometers'")?
-                    as u32;
-
-                let car_type = match car_type.as_str() {
-                    "small" => json_models::CarType::Small,
-                    "sports" => json_models::CarType::Sports,
-                    "luxury" => json_models::CarType::Luxury,
-                    "family" => json_models::CarType::Family,
-                    _ => return Err("Invalid car type".into()),
-                };
-                let idx = dense_store.all.len() as u32;
-                let offer = db_models::Offer {
-                    idx,
-                    id,
-                    data,
-                    most_specific_region_id,
-                    start_date,
-                    end_date,
-                    number_seats,
-                    price,
-                    car_type,
-                    has_vollkasko,
-                    free_kilometers,
-                };
-
-                region_tree.insert_offer(most_specific_region_id as u8, &offer);
-                dense_store.all.push(offer);
-            }
+    let mut body = req.into_body();
+    let mut summary = PostResponseBodyModel::default();
+    let mut batch = Vec::with_capacity(CONFIG.insert_batch_size);
+    let mut received = 0;
+
+    let status = loop {
+        let Some(frame) = body.frame().await else {
+            let finished = decoder.finish(|decoded| match decoded {
+                Ok(offer) => batch.push(offer),
+                Err(_) => summary.rejected += 1,
+            });
+            break match finished {
+                Ok(()) => StatusCode::OK,
+                Err(err) => {
+                    eprintln!("Error parsing offers: {}", err);
+                    StatusCode::BAD_REQUEST
+                }
+            };
+        };
+        let frame = match frame {
+            Ok(frame) => frame,
             Err(err) => {
-                // Handle parsing errors
-                eprintln!("Error parsing JSON array element: {:?}", err);
-                return Ok(Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(full("Invalid JSON format"))?);
+                eprintln!("Error reading offers: {}", err);
+                break StatusCode::BAD_REQUEST;
             }
+        };
+        let Ok(chunk) = frame.into_data() else {
+            continue;
+        };
+        received += chunk.len();
+        if received > max_body_bytes {
+            break StatusCode::PAYLOAD_TOO_LARGE;
         }
-    }
 
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(full(OFFER_CREATED))?;
-    Ok(response)
+        let decoded = decoder.feed(&chunk, |decoded| match decoded {
+            Ok(offer) => batch.push(offer),
+            Err(_) => summary.rejected += 1,
+        });
+        if let Err(err) = decoded {
+            eprintln!("Error parsing offers: {}", err);
+            break StatusCode::BAD_REQUEST;
+        }
+
+        if batch.len() >= CONFIG.insert_batch_size
+            && !flush_offers(manager, &mut batch, &mut summary).await
+        {
+            break StatusCode::INSUFFICIENT_STORAGE;
+        }
+    };
+
+    // Offers that were complete before an error are kept; the summary tells
+    // the client how far the upload got.
+    let status = if flush_offers(manager, &mut batch, &mut summary).await {
+        status
+    } else {
+        StatusCode::INSUFFICIENT_STORAGE
+    };
+    post_summary_response(status, &summary)
 }
 
-async fn handle_get_offers_request(
-    req: Request<IncomingBody>,
+/// Inserts the pending batch. Returns false, rejecting the whole batch, if it
+/// would push memory usage past `CONFIG.memory_limit_bytes`.
+async fn flush_offers(
     manager: &DBManager,
+    batch: &mut Vec<db_models::Offer>,
+    summary: &mut PostResponseBodyModel,
+) -> bool {
+    let offers = std::mem::replace(batch, Vec::with_capacity(CONFIG.insert_batch_size));
+    let offer_count = offers.len() as u32;
+    if offer_count == 0 {
+        return true;
+    }
+    if !manager.has_capacity_for(&offers).await {
+        summary.rejected += offer_count;
+        return false;
+    }
+    let accepted = manager.insert_offers(offers).await;
+    summary.accepted += accepted;
+    summary.rejected += offer_count - accepted;
+    true
+}
+
+fn post_summary_response(
+    status: StatusCode,
+    summary: &PostResponseBodyModel,
 ) -> Result<Response<BoxBody>> {
-    let query = parsing::parse_request_offer(&req.uri().query().unwrap());
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(sonic_rs::to_string(summary)?))?)
+}
 
-    let (response, status_code) = match manager.query_for(query).await {
-        Ok(res) => {
-            // normally use res but now mock
-            let json = unsafe { sonic_rs::to_string(&res).unwrap() };
+/// Parses and validates the search parameters shared by `GET /api/offers`,
+/// `GET /api/offers/count` and `HEAD /api/offers`. Errors carry the body of
+/// the 400 response. Range facets are only checked if they are selected,
+/// with `default_facets` applying when the request has no `facets=`.
+async fn parse_search<B>(
+    req: &Request<B>,
+    manager: &DBManager,
+    default_facets: FacetSelection,
+) -> std::result::Result<RequestOffer, String> {
+    let bad_request = || String::from_utf8_lossy(BAD_REQUEST).into_owned();
+    let query = parsing::parse_request_offer(req.uri().query().ok_or_else(bad_request)?);
+    let max_buckets = CONFIG.max_facet_buckets;
+    let index_tree = manager.index_tree_lock.read().await;
+    if !query
+        .region_ids
+        .iter()
+        .chain(&query.excluded_region_ids)
+        .all(|&region_id| index_tree.contains_region(region_id))
+    {
+        return Err(bad_request());
+    }
+    drop(index_tree);
+    if query.car_type.is_some_and(|car_type| !car_type.is_known()) {
+        return Err(format!(
+            "Unknown carType; configured types are: {}",
+            CAR_TYPES.names().join(", ")
+        ));
+    }
+    if query.currency.is_some_and(|currency| !currency.is_valid()) {
+        let known = manager.rates_lock.read().await.currencies();
+        let mut message =
+            "Invalid currency; expected a three-letter ISO 4217 code such as EUR".to_string();
+        if !known.is_empty() {
+            let known: Vec<String> = known.iter().map(Currency::to_string).collect();
+            message += &format!(", rates are configured for: {}", known.join(", "));
+        }
+        return Err(message);
+    }
+    let facets = match query.facets.as_deref() {
+        None => default_facets,
+        spec => FacetSelection::parse(spec).map_err(|_| bad_request())?,
+    };
+    if (facets.price_ranges
+        && RangeFacet::new(
+            query.price_range_width,
+            query.price_buckets.as_deref(),
+            max_buckets,
+        )
+        .is_err())
+        || (facets.free_kilometers
+            && RangeFacet::new(
+                query.min_free_kilometer_width,
+                query.free_kilometer_buckets.as_deref(),
+                max_buckets,
+            )
+            .is_err())
+        || manager
+            .schema
+            .compile_query(&query.attribute_filters)
+            .is_err()
+        || StatsRequest::parse(query.stats.as_deref()).is_err()
+        || GeoFilter::parse(query.near.as_deref(), query.radius_km).is_err()
+    {
+        return Err(bad_request());
+    }
+    Ok(query)
+}
 
-            (full(json), StatusCode::OK)
+async fn handle_get_offers_request<B>(
+    req: Request<B>,
+    manager: &DBManager,
+) -> Result<Response<BoxBody>> {
+    let query = match parse_search(&req, manager, FacetSelection::ALL).await {
+        Ok(query) => query,
+        Err(message) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(full(message))?)
         }
+    };
+
+    let (response, status_code) = match manager.query_json(query).await {
+        Ok(json) => (full(json), StatusCode::OK),
         Err(err) => {
             println!("{:?}", err);
             (
@@ -177,6 +261,50 @@ async fn handle_get_offers_request(
     Ok(response)
 }
 
+async fn count_offers_response<B>(
+    req: Request<B>,
+    manager: &DBManager,
+) -> Result<Response<BoxBody>> {
+    let query = match parse_search(&req, manager, FacetSelection::NONE).await {
+        Ok(query) => query,
+        Err(message) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(full(message))?)
+        }
+    };
+    let model = manager.count_for(query).await?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(sonic_rs::to_string(&model)?))?)
+}
+
+/// Answers whether any offer matches: 200 if one does, 404 otherwise, with
+/// the number of matches in `X-Total-Count` either way.
+async fn head_offers_response<B>(
+    req: Request<B>,
+    manager: &DBManager,
+) -> Result<Response<BoxBody>> {
+    let Ok(mut query) = parse_search(&req, manager, FacetSelection::NONE).await else {
+        return Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(full(""))?);
+    };
+    query.facets = Some("none".to_string());
+    query.stats = None;
+    let count = manager.count_for(query).await?.count;
+    let status = if count > 0 {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+    Ok(Response::builder()
+        .status(status)
+        .header("X-Total-Count", count)
+        .body(full(""))?)
+}
+
 async fn delete_offer_request(manager: &DBManager) -> Result<Response<BoxBody>> {
     let (response, status_code) = match manager.cleanup().await {
         Ok(_) => (OFFERS_CLEANED_UP, StatusCode::OK),
@@ -190,15 +318,257 @@ async fn delete_offer_request(manager: &DBManager) -> Result<Response<BoxBody>>
     Ok(response)
 }
 
+fn metrics_response() -> Result<Response<BoxBody>> {
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(sonic_rs::to_string(&METRICS.to_model())?))?)
+}
+
+/// Collects a request body of at most `CONFIG.max_body_bytes`. Returns
+/// `None` for larger bodies.
+async fn collect_limited(body: IncomingBody) -> Result<Option<Bytes>> {
+    match Limited::new(body, CONFIG.max_body_bytes).collect().await {
+        Ok(collected) => Ok(Some(collected.to_bytes())),
+        Err(err) if err.is::<LengthLimitError>() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+async fn hold_response(
+    req: Request<IncomingBody>,
+    offer_id: &str,
+    manager: &DBManager,
+) -> Result<Response<BoxBody>> {
+    let Some(body) = collect_limited(req.into_body()).await? else {
+        return Ok(Response::builder()
+            .status(StatusCode::PAYLOAD_TOO_LARGE)
+            .body(full(PAYLOAD_TOO_LARGE))?);
+    };
+    let hold_request = if body.is_empty() {
+        HoldRequestModel::default()
+    } else {
+        match sonic_rs::from_slice::<HoldRequestModel>(&body) {
+            Ok(hold_request) => hold_request,
+            Err(err) => {
+                eprintln!("Error parsing hold request: {}", err);
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(full(BAD_REQUEST))?);
+            }
+        }
+    };
+    let ttl_secs = hold_request.ttl_seconds.unwrap_or(CONFIG.hold_ttl_secs);
+
+    match manager
+        .hold_offer(offer_id, ttl_secs, expiry::now_millis())
+        .await
+    {
+        Ok(hold) => {
+            let model = HoldResponseModel {
+                id: offer_id.to_string(),
+                held_until: hold.until,
+                hold_token: hold.token,
+            };
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(full(sonic_rs::to_string(&model)?))?)
+        }
+        Err(HoldError::UnknownOffer) => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(full(NOTFOUND))?),
+        Err(HoldError::AlreadyHeld) => Ok(Response::builder()
+            .status(StatusCode::CONFLICT)
+            .body(full(OFFER_ALREADY_HELD))?),
+    }
+}
+
+async fn release_response(offer_id: &str, manager: &DBManager) -> Result<Response<BoxBody>> {
+    let status = if manager.release_offer(offer_id, expiry::now_millis()).await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    };
+    Ok(Response::builder().status(status).body(full(""))?)
+}
+
+async fn booking_response(
+    req: Request<IncomingBody>,
+    offer_id: &str,
+    manager: &DBManager,
+) -> Result<Response<BoxBody>> {
+    let Some(body) = collect_limited(req.into_body()).await? else {
+        return Ok(Response::builder()
+            .status(StatusCode::PAYLOAD_TOO_LARGE)
+            .body(full(PAYLOAD_TOO_LARGE))?);
+    };
+    let booking = match sonic_rs::from_slice::<BookingRequestModel>(&body) {
+        Ok(booking) if booking.start_date < booking.end_date => booking,
+        Ok(_) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(full(BAD_REQUEST))?)
+        }
+        Err(err) => {
+            eprintln!("Error parsing booking request: {}", err);
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(full(BAD_REQUEST))?);
+        }
+    };
+
+    match manager
+        .book_offer(
+            offer_id,
+            booking.start_date,
+            booking.end_date,
+            booking.hold_token,
+            expiry::now_millis(),
+        )
+        .await
+    {
+        Ok(remaining) => {
+            let model = BookingResponseModel {
+                id: offer_id.to_string(),
+                free_intervals: remaining
+                    .into_iter()
+                    .map(|(start_date, end_date)| AvailabilityInterval {
+                        start_date,
+                        end_date,
+                    })
+                    .collect(),
+            };
+            Ok(Response::builder()
+                .status(StatusCode::CREATED)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(full(sonic_rs::to_string(&model)?))?)
+        }
+        Err(BookingError::UnknownOffer) => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(full(NOTFOUND))?),
+        Err(BookingError::Unavailable) => Ok(Response::builder()
+            .status(StatusCode::CONFLICT)
+            .body(full(OFFER_UNAVAILABLE))?),
+    }
+}
+
+/// Splits a `/api/offers/{id}/{resource}` path into the offer ID and resource.
+fn offer_resource(path: &str) -> Option<(&str, &str)> {
+    let (offer_id, resource) = path.strip_prefix("/api/offers/")?.split_once('/')?;
+    (!offer_id.is_empty()).then_some((offer_id, resource))
+}
+
+/// The region id of a `/api/regions/{id}/path` request.
+fn region_path(path: &str) -> Option<&str> {
+    path.strip_prefix("/api/regions/")?.strip_suffix("/path")
+}
+
+async fn region_path_response(
+    region_id: Option<&str>,
+    manager: &DBManager,
+) -> Result<Response<BoxBody>> {
+    let index_tree = manager.index_tree_lock.read().await;
+    let Some(path) = region_id
+        .and_then(|region_id| region_id.parse().ok())
+        .and_then(|region_id| index_tree.region_path(region_id))
+    else {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(full(NOTFOUND))?);
+    };
+    let model = RegionPathResponseModel {
+        path: path
+            .into_iter()
+            .map(|(region_id, name)| RegionModel {
+                region_id,
+                name: name.to_string(),
+            })
+            .collect(),
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(sonic_rs::to_string(&model)?))?)
+}
+
+async fn reload_rates_response(manager: &DBManager) -> Result<Response<BoxBody>> {
+    let Some(path) = &CONFIG.rates_path else {
+        return Ok(Response::builder()
+            .status(StatusCode::CONFLICT)
+            .body(full(NO_RATES_FILE))?);
+    };
+    let rates = match ExchangeRates::load(path) {
+        Ok(rates) => rates,
+        Err(err) => {
+            eprintln!("Error loading exchange rates: {}", err);
+            return Ok(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(full(INTERNAL_SERVER_ERROR))?);
+        }
+    };
+    let model = RatesResponseModel {
+        currencies: rates.len() as u64,
+    };
+    manager.set_rates(rates).await;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(sonic_rs::to_string(&model)?))?)
+}
+
+async fn stats_response(manager: &DBManager) -> Result<Response<BoxBody>> {
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(sonic_rs::to_string(&manager.memory_stats().await)?))?)
+}
+
+async fn compact_response(manager: &DBManager) -> Result<Response<BoxBody>> {
+    let reclaimed = manager.compact().await;
+    METRICS.record_compaction(reclaimed);
+    let model = CompactionResponseModel {
+        reclaimed_slots: reclaimed as u64,
+    };
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(sonic_rs::to_string(&model)?))?)
+}
+
 async fn api_handler(
     req: Request<IncomingBody>,
     manager: Arc<DBManager>,
 ) -> Result<Response<BoxBody>> {
+    if let Some((offer_id, resource)) = offer_resource(req.uri().path()) {
+        let offer_id = offer_id.to_string();
+        return match (req.method(), resource) {
+            (&Method::POST, "hold") => hold_response(req, &offer_id, &manager).await,
+            (&Method::DELETE, "hold") => release_response(&offer_id, &manager).await,
+            (&Method::POST, "bookings") => booking_response(req, &offer_id, &manager).await,
+            (_, "hold" | "bookings") => Ok(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(full(""))?),
+            _ => Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(full(NOTFOUND))?),
+        };
+    }
+
     match (req.method(), req.uri().path()) {
         (&Method::GET, "/") => Ok(Response::new(full("clueless"))),
         (&Method::POST, "/api/offers") => api_post_response(req, &manager).await,
         (&Method::GET, "/api/offers") => handle_get_offers_request(req, &manager).await,
+        (&Method::HEAD, "/api/offers") => head_offers_response(req, &manager).await,
+        (&Method::GET, "/api/offers/count") => count_offers_response(req, &manager).await,
         (&Method::DELETE, "/api/offers") => delete_offer_request(&manager).await,
+        (&Method::GET, path) if path.starts_with("/api/regions/") => {
+            region_path_response(region_path(path), &manager).await
+        }
+        (&Method::GET, "/admin/metrics") => metrics_response(),
+        (&Method::POST, "/admin/rates/reload") => reload_rates_response(&manager).await,
+        (&Method::GET, "/admin/stats") => stats_response(&manager).await,
+        (&Method::POST, "/admin/compact") => compact_response(&manager).await,
         _ => {
             // Return 404 not found response.
             Ok(Response::builder()
@@ -217,7 +587,25 @@ fn full<T: Into<Bytes>>(chunk: T) -> BoxBody {
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<()> {
-    let db_manager = Arc::new(DBManager::new());
+    // Fail at startup rather than on the first upload if the schema is invalid.
+    Lazy::force(&attributes::SCHEMA);
+    let db_manager = match &CONFIG.snapshot_path {
+        Some(path) => {
+            let (dense_store, index_tree) = snapshot::read_snapshot(path)?;
+            println!(
+                "Loaded {} offers from {}",
+                dense_store.all.len(),
+                path.display()
+            );
+            DBManager::from_parts(index_tree, dense_store)
+        }
+        None => DBManager::new(),
+    };
+    if let Some(path) = &CONFIG.rates_path {
+        db_manager.set_rates(ExchangeRates::load(path)?).await;
+    }
+    let db_manager = Arc::new(db_manager);
+    tokio::spawn(expiry::run_eviction_loop(db_manager.clone()));
     // db_manager.init().await?;
     let region_tree = IndexTree::populate_with_regions(&ROOT_REGION);
     if cfg!(debug_assertions) {
@@ -245,3 +633,45 @@ async fn main() -> Result<()> {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEARCH: &str = "regionID=0&timeRangeStart=0&timeRangeEnd=100&numberDays=1\
+        &sortOrder=price-asc&page=0&pageSize=10";
+
+    #[tokio::test]
+    async fn range_facet_widths_are_only_required_when_selected() {
+        let manager = DBManager::new();
+        let request = |path: &str, query: &str| {
+            Request::get(format!("{}?{}{}", path, SEARCH, query))
+                .body(())
+                .unwrap()
+        };
+
+        let response = count_offers_response(request("/api/offers/count", ""), &manager)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let query = "&facets=carTypeCounts";
+        let response = handle_get_offers_request(request("/api/offers", query), &manager)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = head_offers_response(request("/api/offers", ""), &manager)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let query = "&facets=priceRanges";
+        let response = count_offers_response(request("/api/offers/count", query), &manager)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = handle_get_offers_request(request("/api/offers", ""), &manager)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
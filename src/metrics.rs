@@ -0,0 +1,57 @@
+use crate::json_models::MetricsResponseModel;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Process-wide counters, reported by `GET /admin/metrics`.
+pub struct Metrics {
+    pub eviction_runs: AtomicU64,
+    pub evicted_expired: AtomicU64,
+    pub evicted_started: AtomicU64,
+    pub compactions: AtomicU64,
+    pub reclaimed_slots: AtomicU64,
+    pub query_cache_hits: AtomicU64,
+    pub query_cache_misses: AtomicU64,
+    /// Searches that took their car type, vollkasko and seat facets from
+    /// the index block summaries.
+    pub summarized_searches: AtomicU64,
+}
+
+pub static METRICS: Metrics = Metrics {
+    eviction_runs: AtomicU64::new(0),
+    evicted_expired: AtomicU64::new(0),
+    evicted_started: AtomicU64::new(0),
+    compactions: AtomicU64::new(0),
+    reclaimed_slots: AtomicU64::new(0),
+    query_cache_hits: AtomicU64::new(0),
+    query_cache_misses: AtomicU64::new(0),
+    summarized_searches: AtomicU64::new(0),
+};
+
+impl Metrics {
+    /// Counts a compaction that reclaimed `reclaimed` slots; no-ops are ignored.
+    pub fn record_compaction(&self, reclaimed: usize) {
+        if reclaimed > 0 {
+            self.compactions.fetch_add(1, Ordering::Relaxed);
+            self.reclaimed_slots
+                .fetch_add(reclaimed as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn to_model(&self) -> MetricsResponseModel {
+        let hits = self.query_cache_hits.load(Ordering::Relaxed);
+        let misses = self.query_cache_misses.load(Ordering::Relaxed);
+        MetricsResponseModel {
+            eviction_runs: self.eviction_runs.load(Ordering::Relaxed),
+            evicted_expired: self.evicted_expired.load(Ordering::Relaxed),
+            evicted_started: self.evicted_started.load(Ordering::Relaxed),
+            compactions: self.compactions.load(Ordering::Relaxed),
+            reclaimed_slots: self.reclaimed_slots.load(Ordering::Relaxed),
+            query_cache_hits: hits,
+            query_cache_misses: misses,
+            query_cache_hit_rate: match hits + misses {
+                0 => 0.0,
+                lookups => hits as f64 / lookups as f64,
+            },
+            summarized_searches: self.summarized_searches.load(Ordering::Relaxed),
+        }
+    }
+}
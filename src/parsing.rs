@@ -1,17 +1,9 @@
-use nom::{
-    bytes::complete::{tag, take_until},
-    character::complete::{char, digit1},
-    combinator::{map_res, opt},
-    multi::separated_list1,
-    sequence::{separated_pair, terminated},
-    IResult,
-};
-use std::str::FromStr;
-use crate::GenericError;
-use crate::json_models::{GetReponseBodyModel, SortOrder};
-use crate::json_models::CarType;
+use crate::car_types::CarType;
+use crate::currency::Currency;
 use crate::json_models::RequestOffer;
+use crate::json_models::SortOrder;
 use crate::json_models::SortOrder::PriceAsc;
+use std::str::FromStr;
 
 impl FromStr for SortOrder {
     type Err = ();
@@ -35,20 +27,6 @@ impl SortOrder {
     }
 }
 
-impl FromStr for CarType {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "small" => Ok(CarType::Small),
-            "sports" => Ok(CarType::Sports),
-            "luxury" => Ok(CarType::Luxury),
-            "family" => Ok(CarType::Family),
-            _ => Err(()),
-        }
-    }
-}
-
 // #[derive(Debug)]
 // pub struct RequestOffer {
 //     pub region_id: u8,
@@ -68,17 +46,10 @@ impl FromStr for CarType {
 //     pub min_free_kilometer: Option<u32>,
 // }
 
-fn parse_key_value(input: &str) -> IResult<&str, (&str, &str)> {
-    separated_pair(take_until("="), char('='), take_until("&"))(input)
-}
-
-fn parse_query_string(input: &str) -> IResult<&str, Vec<(&str, &str)>> {
-    separated_list1(char('&'), terminated(parse_key_value, opt(char('&'))))(input)
-}
-
 pub fn parse_request_offer(query: &str) -> RequestOffer {
     // let (_, pairs) = parse_query_string(query).ok()?;
-    let mut region_id = 0u8;
+    let mut region_ids = Vec::new();
+    let mut excluded_region_ids = Vec::new();
     let mut time_range_start = 0u64;
     let mut time_range_end = 0u64;
     let mut number_days = 0u32;
@@ -93,60 +64,115 @@ pub fn parse_request_offer(query: &str) -> RequestOffer {
     let mut car_type = None;
     let mut only_vollkasko = None;
     let mut min_free_kilometers = None;
-
-    query.split('&').for_each(|pair | {
+    let mut currency = None;
+    let mut attribute_filters = Vec::new();
+    let mut price_buckets = None;
+    let mut free_kilometer_buckets = None;
+    let mut stats = None;
+    let mut facets = None;
+    let mut include_region = false;
+    let mut near = None;
+    let mut radius_km = None;
+
+    query.split('&').for_each(|pair| {
         // oh no
         unsafe {
             //anyways
-                let (key, value) = {
-                    let mut split = pair.splitn(2, '=');
-                    (split.next().unwrap_unchecked(), split.next().unwrap_unchecked())
-                };
-
-                match key {
-                    "regionID" => region_id = value.parse::<u8>().unwrap_unchecked(),
-                    "timeRangeStart" => time_range_start = value.parse::<u64>().unwrap_unchecked(),
-                    "timeRangeEnd" => time_range_end = value.parse::<u64>().unwrap_unchecked(),
-                    "numberDays" => number_days = value.parse::<u32>().unwrap_unchecked(),
-                    "sortOrder" => sort_order = SortOrder::fast_from_str(value),
-                    "page" => page = value.parse::<u32>().unwrap_unchecked(),
-                    "pageSize" => page_size = value.parse::<u32>().unwrap_unchecked(),
-                    "priceRangeWidth" => price_range_width = value.parse::<u32>().unwrap_unchecked(),
-                    "minFreeKilometerWidth" => min_free_kilometer_width = value.parse::<u32>().unwrap_unchecked(),
-                    "minNumberSeats" => min_number_seats = value.parse::<u32>().unwrap_unchecked().into(),
-                    "minPrice" => min_price = value.parse::<u32>().unwrap_unchecked().into(),
-                    "maxPrice" => max_price = value.parse::<u32>().unwrap_unchecked().into(),
-                    "carType" => car_type = value.parse::<CarType>().unwrap_unchecked().into(),
-                    "onlyVollkasko" => only_vollkasko = value.parse::<bool>().unwrap_unchecked().into(),
-                    "minFreeKilometer" => min_free_kilometers = value.parse::<u32>().unwrap_unchecked().into(),
-                    _ => {} // Skip unknown keys for simplicity
+            let (key, value) = {
+                let mut split = pair.splitn(2, '=');
+                (
+                    split.next().unwrap_unchecked(),
+                    split.next().unwrap_unchecked(),
+                )
+            };
+
+            match key {
+                "regionID" => parse_region_ids(value, &mut region_ids),
+                "excludeRegionID" => parse_region_ids(value, &mut excluded_region_ids),
+                "timeRangeStart" => time_range_start = value.parse::<u64>().unwrap_unchecked(),
+                "timeRangeEnd" => time_range_end = value.parse::<u64>().unwrap_unchecked(),
+                "numberDays" => number_days = value.parse::<u32>().unwrap_unchecked(),
+                "sortOrder" => sort_order = SortOrder::fast_from_str(value),
+                "page" => page = value.parse::<u32>().unwrap_unchecked(),
+                "pageSize" => page_size = value.parse::<u32>().unwrap_unchecked(),
+                "priceRangeWidth" => price_range_width = value.parse::<u32>().unwrap_or(0),
+                "minFreeKilometerWidth" => {
+                    min_free_kilometer_width = value.parse::<u32>().unwrap_or(0)
+                }
+                "priceBuckets" => price_buckets = Some(value.to_string()),
+                "freeKilometerBuckets" => free_kilometer_buckets = Some(value.to_string()),
+                "stats" => stats = Some(value.to_string()),
+                "facets" => facets = Some(value.to_string()),
+                "includeRegion" => include_region = value == "true",
+                "near" => near = Some(value.to_string()),
+                "radiusKm" => radius_km = Some(value.parse().unwrap_or(f64::NAN)),
+                "minNumberSeats" => {
+                    min_number_seats = value.parse::<u32>().unwrap_unchecked().into()
+                }
+                "minPrice" => min_price = value.parse::<u32>().unwrap_unchecked().into(),
+                "maxPrice" => max_price = value.parse::<u32>().unwrap_unchecked().into(),
+                "carType" => car_type = Some(value.parse().unwrap_or(CarType::UNKNOWN)),
+                "onlyVollkasko" => only_vollkasko = value.parse::<bool>().unwrap_unchecked().into(),
+                "minFreeKilometer" => {
+                    min_free_kilometers = value.parse::<u32>().unwrap_unchecked().into()
                 }
+                "currency" => currency = Some(value.parse().unwrap_or(Currency::INVALID)),
+                _ if key.starts_with("attr.") => {
+                    attribute_filters.push((key["attr.".len()..].to_string(), value.to_string()))
+                }
+                _ => {} // Skip unknown keys for simplicity
+            }
         }
     });
 
+    if region_ids.is_empty() {
+        region_ids.push(0);
+    }
+
     RequestOffer {
-        region_id: region_id,
-        time_range_start: time_range_start,
-        time_range_end: time_range_end,
-        number_days: number_days,
-        sort_order: sort_order,
-        page: page,
-        page_size: page_size,
-        price_range_width: price_range_width,
-        min_free_kilometer_width: min_free_kilometer_width,
-        min_number_seats: min_number_seats,
-        min_price: min_price,
-        max_price: max_price,
-        car_type: car_type,
-        only_vollkasko: only_vollkasko,
+        region_ids,
+        excluded_region_ids,
+        time_range_start,
+        time_range_end,
+        number_days,
+        sort_order,
+        page,
+        page_size,
+        price_range_width,
+        min_free_kilometer_width,
+        min_number_seats,
+        min_price,
+        max_price,
+        car_type,
+        only_vollkasko,
         min_free_kilometer: min_free_kilometers,
+        currency,
+        attribute_filters,
+        price_buckets,
+        free_kilometer_buckets,
+        stats,
+        facets,
+        include_region,
+        near,
+        radius_km,
     }
 }
 
+/// Appends a comma-separated list of region ids. Ids that do not parse are
+/// kept as `u8::MAX`, which is not a region, so the search is rejected.
+fn parse_region_ids(value: &str, region_ids: &mut Vec<u8>) {
+    region_ids.extend(
+        value
+            .replace("%2C", ",")
+            .split(',')
+            .map(|id| id.parse().unwrap_or(u8::MAX)),
+    );
+}
+
 #[cfg(test)]
 mod tests {
-    use std::env;
     use super::*;
+    use std::env;
 
     #[test]
     fn test_parse_request_offer() {
@@ -156,90 +182,3 @@ mod tests {
         assert_eq!(true, true);
     }
 }
-
-
-impl GetReponseBodyModel {
-    pub unsafe fn to_json(&self) -> String {
-        let mut json = String::with_capacity(1024); // Preallocate memory to reduce reallocations
-
-        json.push('{');
-
-        // Offers
-        json.push_str("\"offers\":[");
-        for (i, offer) in self.offers.iter().enumerate() {
-            if i > 0 {
-                json.push(',');
-            }
-            json.push('{');
-            json.push_str("\"ID\":\"");
-            json.push_str(&offer.ID);
-            json.push_str("\",\"data\":\"");
-            json.push_str(&offer.data);
-            json.push_str("\"}");
-        }
-        json.push(']');
-
-        // Price ranges
-        json.push_str(",\"price_ranges\":[");
-        for (i, range) in self.price_ranges.iter().enumerate() {
-            if i > 0 {
-                json.push(',');
-            }
-            json.push_str(&format!(
-                "{{\"start\":{},\"end\":{},\"count\":{}}}",
-                range.start, range.end, range.count
-            ));
-        }
-        json.push(']');
-
-        // Car type counts
-        json.push_str(",\"car_type_counts\":{");
-        json.push_str(&format!(
-            "\"small\":{},\"sports\":{},\"luxury\":{},\"family\":{}",
-            self.car_type_counts.small,
-            self.car_type_counts.sports,
-            self.car_type_counts.luxury,
-            self.car_type_counts.family
-        ));
-        json.push('}');
-
-        // Seat counts
-        json.push_str(",\"seats_count\":[");
-        for (i, seat) in self.seats_count.iter().enumerate() {
-            if i > 0 {
-                json.push(',');
-            }
-            json.push_str(&format!(
-                "{{\"count\":{},\"number_seats\":{}}}",
-                seat.count, seat.number_seats
-            ));
-        }
-        json.push(']');
-
-        // Free kilometer range
-        json.push_str(",\"free_kilometer_range\":[");
-        for (i, range) in self.free_kilometer_range.iter().enumerate() {
-            if i > 0 {
-                json.push(',');
-            }
-            json.push_str(&format!(
-                "{{\"start\":{},\"end\":{},\"count\":{}}}",
-                range.start, range.end, range.count
-            ));
-        }
-        json.push(']');
-
-        // Vollkasko count
-        json.push_str(",\"vollkasko_count\":{");
-        json.push_str(&format!(
-            "\"true_count\":{},\"false_count\":{}",
-            self.vollkasko_count.true_count, self.vollkasko_count.false_count
-        ));
-        json.push('}');
-
-        json.push('}');
-
-        json
-    }
-}
-
@@ -0,0 +1,129 @@
+use crate::json_models::RequestOffer;
+use bytes::Bytes;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+/// A serialized search response and what it was computed from.
+struct CachedSearch {
+    /// `(region_id, subtree_version)` of every region the search included.
+    versions: Vec<(u8, u64)>,
+    /// When the earliest hold in the store lapses, which may reveal an offer
+    /// the search skipped.
+    valid_until: u64,
+    /// Cloning shares the buffer, so a hit is served without copying.
+    json: Bytes,
+}
+
+/// Bounded LRU cache of `GET /api/offers` responses, keyed by the normalised
+/// request. An entry is only served while the subtree versions of its
+/// regions are unchanged, see [`crate::index_tree::IndexTree::touch_region`].
+pub struct QueryCache {
+    entries: Option<Mutex<LruCache<String, CachedSearch>>>,
+}
+
+impl QueryCache {
+    /// A cache of at most `capacity` responses; 0 disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: NonZeroUsize::new(capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.entries.is_some()
+    }
+
+    /// The cache key of a search priced with the exchange rates of
+    /// `rates_generation`. Requests that only differ in the order of their
+    /// region lists or attribute filters share a key.
+    pub fn key(request_offer: &mut RequestOffer, rates_generation: u64) -> String {
+        for regions in [
+            &mut request_offer.region_ids,
+            &mut request_offer.excluded_region_ids,
+        ] {
+            regions.sort_unstable();
+            regions.dedup();
+        }
+        request_offer.attribute_filters.sort_unstable();
+        let request = sonic_rs::to_string(request_offer).unwrap_or_default();
+        format!("{}:{}", rates_generation, request)
+    }
+
+    /// Returns the cached response for `key` if it is still current.
+    pub fn get(&self, key: &str, versions: &[(u8, u64)], now: u64) -> Option<Bytes> {
+        let mut entries = self.entries.as_ref()?.lock().unwrap();
+        let current = entries
+            .get(key)
+            .is_some_and(|entry| entry.versions == versions && now < entry.valid_until);
+        if current {
+            entries.get(key).map(|entry| entry.json.clone())
+        } else {
+            entries.pop(key);
+            None
+        }
+    }
+
+    pub fn put(&self, key: String, versions: Vec<(u8, u64)>, valid_until: u64, json: Bytes) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().put(
+                key,
+                CachedSearch {
+                    versions,
+                    valid_until,
+                    json,
+                },
+            );
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_entries_while_their_versions_are_current() {
+        let cache = QueryCache::new(1);
+        cache.put(
+            "a".to_string(),
+            vec![(7, 1)],
+            100,
+            Bytes::from_static(b"[]"),
+        );
+        assert_eq!(cache.get("a", &[(7, 1)], 50).as_deref(), Some(&b"[]"[..]));
+        assert_eq!(cache.get("a", &[(7, 2)], 50), None);
+        // Stale entries are dropped.
+        assert_eq!(cache.get("a", &[(7, 1)], 50), None);
+
+        cache.put(
+            "a".to_string(),
+            vec![(7, 1)],
+            100,
+            Bytes::from_static(b"[]"),
+        );
+        assert_eq!(cache.get("a", &[(7, 1)], 100), None);
+
+        cache.put(
+            "a".to_string(),
+            vec![(7, 1)],
+            100,
+            Bytes::from_static(b"[]"),
+        );
+        cache.put(
+            "b".to_string(),
+            vec![(7, 1)],
+            100,
+            Bytes::from_static(b"{}"),
+        );
+        assert_eq!(cache.get("a", &[(7, 1)], 50), None);
+        assert!(QueryCache::new(0).get("b", &[(7, 1)], 50).is_none());
+    }
+}
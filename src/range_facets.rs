@@ -0,0 +1,186 @@
+use fxhash::FxHashMap;
+use itertools::Itertools;
+
+/// Counts values into the buckets of a range facet such as `priceRanges`.
+///
+/// Buckets are either `width` wide, or delimited by a bucket spec:
+/// explicit ascending boundaries like `0,5000,10000,20000`, or `log:<base>`
+/// for boundaries `0, 1, base, base², …`. With boundaries, values below the
+/// first boundary fall into a bucket starting at 0 and the last bucket is
+/// open-ended.
+#[derive(Debug, Clone)]
+pub struct RangeFacet {
+    width: u32,
+    /// Bucket starts, ascending and beginning with 0. Empty for fixed widths.
+    boundaries: Vec<u32>,
+    by_start: FxHashMap<u32, u32>,
+    by_bucket: Vec<u32>,
+}
+
+impl RangeFacet {
+    pub fn new(width: u32, spec: Option<&str>, max_buckets: usize) -> Result<Self, &'static str> {
+        let boundaries = match spec {
+            None => {
+                if width == 0 {
+                    return Err("Range facet width must be positive");
+                }
+                Vec::new()
+            }
+            Some(spec) => parse_boundaries(spec)?,
+        };
+        if boundaries.len() > max_buckets {
+            return Err("Too many range facet buckets");
+        }
+        Ok(Self {
+            width,
+            by_bucket: vec![0; boundaries.len()],
+            boundaries,
+            by_start: FxHashMap::default(),
+        })
+    }
+
+    /// A facet for searches that do not select it, which nothing is counted
+    /// into.
+    pub fn unused() -> Self {
+        Self {
+            width: 1,
+            boundaries: Vec::new(),
+            by_start: FxHashMap::default(),
+            by_bucket: Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn count(&mut self, value: u32) {
+        if self.boundaries.is_empty() {
+            *self
+                .by_start
+                .entry(value / self.width * self.width)
+                .or_insert(0) += 1;
+        } else {
+            let bucket = self.boundaries.partition_point(|&start| start <= value) - 1;
+            self.by_bucket[bucket] += 1;
+        }
+    }
+
+    /// Adds the counts of a facet with the same buckets.
+    pub fn merge(&mut self, other: RangeFacet) {
+        for (start, count) in other.by_start {
+            *self.by_start.entry(start).or_insert(0) += count;
+        }
+        for (count, other) in self.by_bucket.iter_mut().zip(other.by_bucket) {
+            *count += other;
+        }
+    }
+
+    /// Non-empty buckets as `(start, end, count)`, ascending. Fixed-width
+    /// facets with more than `max_buckets` buckets fold the surplus into a
+    /// last, open-ended bucket.
+    pub fn into_ranges(self, max_buckets: usize) -> Vec<(u32, u32, u32)> {
+        if self.boundaries.is_empty() {
+            let width = self.width;
+            let mut ranges: Vec<_> = self
+                .by_start
+                .into_iter()
+                .sorted_unstable()
+                .map(|(start, count)| (start, start.saturating_add(width), count))
+                .collect();
+            if ranges.len() > max_buckets.max(1) {
+                let surplus = ranges.split_off(max_buckets.max(1) - 1);
+                let count = surplus.iter().map(|(_, _, count)| count).sum();
+                ranges.push((surplus[0].0, u32::MAX, count));
+            }
+            return ranges;
+        }
+        let ends = self.boundaries[1..]
+            .iter()
+            .copied()
+            .chain(std::iter::once(u32::MAX));
+        self.boundaries
+            .iter()
+            .copied()
+            .zip(ends)
+            .zip(self.by_bucket)
+            .filter(|(_, count)| *count > 0)
+            .map(|((start, end), count)| (start, end, count))
+            .collect()
+    }
+}
+
+fn parse_boundaries(spec: &str) -> Result<Vec<u32>, &'static str> {
+    let spec = spec.replace("%2C", ",").replace("%3A", ":");
+    if let Some(base) = spec.strip_prefix("log:") {
+        let base: u64 = base
+            .parse()
+            .ok()
+            .filter(|&base| base >= 2)
+            .ok_or("Invalid logarithmic bucket base")?;
+        let mut boundaries = vec![0];
+        let mut start = 1u64;
+        while start <= u32::MAX as u64 {
+            boundaries.push(start as u32);
+            start *= base;
+        }
+        return Ok(boundaries);
+    }
+    let mut boundaries = spec
+        .split(',')
+        .map(|boundary| boundary.parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "Invalid bucket boundary")?;
+    if !boundaries.windows(2).all(|pair| pair[0] < pair[1]) {
+        return Err("Bucket boundaries must ascend");
+    }
+    if boundaries[0] != 0 {
+        boundaries.insert(0, 0);
+    }
+    Ok(boundaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(width: u32, spec: Option<&str>, values: &[u32]) -> Vec<(u32, u32, u32)> {
+        let mut facet = RangeFacet::new(width, spec, 100).unwrap();
+        values.iter().for_each(|&value| facet.count(value));
+        facet.into_ranges(100)
+    }
+
+    #[test]
+    fn counts_into_explicit_and_logarithmic_buckets() {
+        assert_eq!(
+            ranges(0, Some("0,5000,10000,20000"), &[0, 4999, 5000, 25_000]),
+            [(0, 5000, 2), (5000, 10000, 1), (20000, u32::MAX, 1)]
+        );
+        assert_eq!(
+            ranges(0, Some("100%2C200"), &[50, 150]),
+            [(0, 100, 1), (100, 200, 1)]
+        );
+        assert_eq!(
+            ranges(0, Some("log:10"), &[0, 5, 10, 99, 12_345]),
+            [(0, 1, 1), (1, 10, 1), (10, 100, 2), (10_000, 100_000, 1)]
+        );
+        assert_eq!(ranges(10, None, &[5, 12, 18]), [(0, 10, 1), (10, 20, 2)]);
+    }
+
+    #[test]
+    fn caps_fixed_width_buckets() {
+        let mut facet = RangeFacet::new(1, None, 3).unwrap();
+        (0..10).for_each(|value| facet.count(value));
+        assert_eq!(
+            facet.into_ranges(3),
+            [(0, 1, 1), (1, 2, 1), (2, u32::MAX, 8)]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_buckets() {
+        assert!(RangeFacet::new(0, None, 100).is_err());
+        assert!(RangeFacet::new(0, Some("10,5"), 100).is_err());
+        assert!(RangeFacet::new(0, Some("10,,20"), 100).is_err());
+        assert!(RangeFacet::new(0, Some("log:1"), 100).is_err());
+        assert!(RangeFacet::new(0, Some("1,2,3,4"), 3).is_err());
+        assert!(RangeFacet::new(0, Some("log:2"), 100).is_ok());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::db_models::Offer;
+use crate::facets::FacetSelection;
+use crate::json_models::RequestOffer;
+use roaring::RoaringBitmap;
+use std::collections::BTreeMap;
+
+/// Candidate sets covering more than this share of the live offers are not
+/// worth checking; the per-offer filters are cheaper then.
+const MAX_CANDIDATE_SHARE: f64 = 0.5;
+
+/// Bitmaps of the live offers' `idx` per car type, vollkasko flag and
+/// number of seats, so that selective `carType`, `onlyVollkasko` and
+/// `minNumberSeats` filters can narrow a search before offers are loaded
+/// from the dense store.
+#[derive(Debug, Default, Clone)]
+pub struct SecondaryIndexes {
+    /// Indexed by car type id.
+    car_types: Vec<RoaringBitmap>,
+    vollkasko: RoaringBitmap,
+    seats: BTreeMap<u32, RoaringBitmap>,
+}
+
+impl SecondaryIndexes {
+    pub fn from_offers<'a>(offers: impl IntoIterator<Item = &'a Offer>) -> Self {
+        let mut indexes = Self::default();
+        for offer in offers {
+            indexes.insert(offer);
+        }
+        indexes
+    }
+
+    pub fn insert(&mut self, offer: &Offer) {
+        let car_type = offer.car_type.id() as usize;
+        if self.car_types.len() <= car_type {
+            self.car_types.resize(car_type + 1, RoaringBitmap::new());
+        }
+        self.car_types[car_type].insert(offer.idx);
+        if offer.has_vollkasko {
+            self.vollkasko.insert(offer.idx);
+        }
+        self.seats
+            .entry(offer.number_seats)
+            .or_default()
+            .insert(offer.idx);
+    }
+
+    pub fn remove(&mut self, offer: &Offer) {
+        if let Some(bitmap) = self.car_types.get_mut(offer.car_type.id() as usize) {
+            bitmap.remove(offer.idx);
+        }
+        self.vollkasko.remove(offer.idx);
+        if let Some(bitmap) = self.seats.get_mut(&offer.number_seats) {
+            bitmap.remove(offer.idx);
+            if bitmap.is_empty() {
+                self.seats.remove(&offer.number_seats);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Approximate heap memory of the bitmaps.
+    pub fn memory_bytes(&self) -> usize {
+        self.car_types
+            .iter()
+            .chain([&self.vollkasko])
+            .chain(self.seats.values())
+            .map(RoaringBitmap::serialized_size)
+            .sum()
+    }
+
+    /// The offers a search has to look at given its categorical filters, or
+    /// `None` if that would not narrow it down enough to pay off.
+    ///
+    /// Offers failing a filter whose facet is not selected are never needed.
+    /// Since a facet ignores its own filter, offers failing exactly one of the
+    /// filters whose facet is selected are still needed for that facet.
+    pub fn candidates(
+        &self,
+        request_offer: &RequestOffer,
+        facets: FacetSelection,
+        live_offers: usize,
+    ) -> Option<RoaringBitmap> {
+        let mut required = Vec::new();
+        let mut relaxed = Vec::new();
+        let mut add = |bitmap, facet_selected| {
+            if facet_selected {
+                relaxed.push(bitmap);
+            } else {
+                required.push(bitmap);
+            }
+        };
+        if let Some(car_type) = request_offer.car_type {
+            let bitmap = self.car_types.get(car_type.id() as usize);
+            add(bitmap.cloned().unwrap_or_default(), facets.car_types);
+        }
+        if request_offer.only_vollkasko == Some(true) {
+            add(self.vollkasko.clone(), facets.vollkasko);
+        }
+        if let Some(min_number_seats) = request_offer.min_number_seats {
+            let bitmap = self
+                .seats
+                .range(min_number_seats..)
+                .map(|(_, bitmap)| bitmap);
+            add(
+                bitmap.fold(RoaringBitmap::new(), |all, bitmap| all | bitmap),
+                facets.seats,
+            );
+        }
+
+        // With a single relaxed filter, every offer may fail it.
+        if relaxed.len() >= 2 {
+            let all_but_one = (0..relaxed.len())
+                .map(|skipped| {
+                    intersect(
+                        relaxed
+                            .iter()
+                            .enumerate()
+                            .filter(|&(position, _)| position != skipped)
+                            .map(|(_, bitmap)| bitmap),
+                    )
+                })
+                .fold(RoaringBitmap::new(), |all, bitmap| all | bitmap);
+            required.push(all_but_one);
+        }
+        let candidates = (!required.is_empty()).then(|| intersect(required.iter()))?;
+        (candidates.len() as f64 <= live_offers as f64 * MAX_CANDIDATE_SHARE).then_some(candidates)
+    }
+}
+
+fn intersect<'a>(mut bitmaps: impl Iterator<Item = &'a RoaringBitmap>) -> RoaringBitmap {
+    let first = bitmaps.next().cloned().unwrap_or_default();
+    bitmaps.fold(first, |all, bitmap| all & bitmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn offer(idx: u32) -> Offer {
+        Offer {
+            idx,
+            id: idx.to_string(),
+            end_date: 1,
+            number_seats: 2 + idx % 5,
+            car_type: ["small", "sports", "luxury", "family"][idx as usize % 4]
+                .parse()
+                .unwrap(),
+            has_vollkasko: idx.is_multiple_of(3),
+            ..test_support::blank_offer()
+        }
+    }
+
+    fn search() -> RequestOffer {
+        RequestOffer {
+            region_ids: vec![0],
+            time_range_end: 1,
+            page_size: 10,
+            min_number_seats: Some(5),
+            car_type: Some("sports".parse().unwrap()),
+            only_vollkasko: Some(true),
+            ..test_support::blank_search()
+        }
+    }
+
+    #[test]
+    fn keeps_offers_failing_one_filter_with_a_selected_facet() {
+        let offers: Vec<_> = (0..600).map(offer).collect();
+        let mut indexes = SecondaryIndexes::from_offers(&offers);
+        let passes = |offer: &Offer| {
+            [
+                offer.car_type.to_string() == "sports",
+                offer.has_vollkasko,
+                offer.number_seats >= 5,
+            ]
+        };
+
+        let candidates = indexes
+            .candidates(&search(), FacetSelection::NONE, offers.len())
+            .unwrap();
+        let expected = offers
+            .iter()
+            .filter(|offer| passes(offer).iter().all(|&pass| pass))
+            .count();
+        assert_eq!(candidates.len() as usize, expected);
+
+        let facets = FacetSelection {
+            car_types: true,
+            vollkasko: true,
+            ..FacetSelection::NONE
+        };
+        let candidates = indexes.candidates(&search(), facets, offers.len()).unwrap();
+        for offer in &offers {
+            let [car_type, vollkasko, seats] = passes(offer);
+            let needed = seats && (car_type || vollkasko);
+            assert_eq!(candidates.contains(offer.idx), needed, "{}", offer.idx);
+        }
+
+        // Every offer may fail the one filter with a selected facet.
+        let search = RequestOffer {
+            car_type: None,
+            only_vollkasko: None,
+            ..search()
+        };
+        assert!(indexes
+            .candidates(&search, FacetSelection::ALL, offers.len())
+            .is_none());
+
+        indexes.remove(&offers[1]);
+        indexes.remove(&offers[13]);
+        assert!(!indexes.car_types[1].contains(1) && !indexes.seats[&5].contains(13));
+    }
+}
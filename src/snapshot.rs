@@ -0,0 +1,346 @@
+use crate::attributes::{MISSING, SCHEMA};
+use crate::car_types::{CarType, CAR_TYPES};
+use crate::currency::Currency;
+use crate::db_manager::DenseStore;
+use crate::db_models::Offer;
+use crate::index_tree::{IndexTree, IndexTreeOffer, ROOT_REGION};
+use fxhash::FxHashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// On-disk layout (all integers little-endian):
+///
+/// ```text
+/// "CLUELESS" version:u32
+/// attribute_count:u32 name*    (the attribute schema the offers were encoded with)
+/// car_type_count:u32 name*     (car type names by id)
+/// offer_count:u64  offer*      (in `idx` order, each followed by attribute_count u32 values)
+/// bucket_count:u64 bucket*     (region:u8 flexible:u8 days:u32 len:u64 (start:u64 end:u64 idx:u32)*)
+/// ```
+///
+/// Removed offers are skipped and the remaining ones are renumbered densely.
+/// The free intervals of booked flexible offers are restored from their
+/// index entries. Car types are matched up by name, so the configured list
+/// may be reordered or extended between writing and reading a snapshot.
+/// Strings are stored as `len:u32` followed by their UTF-8 bytes.
+const MAGIC: &[u8; 8] = b"CLUELESS";
+const VERSION: u32 = 6;
+
+/// Writes the store and index to `path`. The snapshot is written next to it
+/// first and renamed into place, so a crash never leaves a truncated file.
+pub fn write_snapshot(path: &Path, store: &DenseStore, tree: &IndexTree) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut w = BufWriter::new(File::create(&tmp_path)?);
+
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&(SCHEMA.len() as u32).to_le_bytes())?;
+    for attribute in &SCHEMA.attributes {
+        write_str(&mut w, &attribute.name)?;
+    }
+    w.write_all(&(CAR_TYPES.len() as u32).to_le_bytes())?;
+    for name in CAR_TYPES.names() {
+        write_str(&mut w, name)?;
+    }
+
+    let new_idx = store.compacted_positions();
+    let live_count = store.all.len() - store.removed_count();
+    w.write_all(&(live_count as u64).to_le_bytes())?;
+    for offer in store
+        .all
+        .iter()
+        .filter(|offer| !store.is_removed(offer.idx))
+    {
+        write_str(&mut w, &offer.id)?;
+        write_str(&mut w, &offer.data)?;
+        w.write_all(&offer.most_specific_region_id.to_le_bytes())?;
+        w.write_all(&offer.start_date.to_le_bytes())?;
+        w.write_all(&offer.end_date.to_le_bytes())?;
+        w.write_all(&offer.number_seats.to_le_bytes())?;
+        w.write_all(&offer.price.to_le_bytes())?;
+        w.write_all(&offer.currency.as_bytes())?;
+        w.write_all(&[
+            offer.car_type.id(),
+            offer.has_vollkasko as u8,
+            offer.flexible as u8,
+        ])?;
+        w.write_all(&offer.free_kilometers.to_le_bytes())?;
+        w.write_all(&offer.expires_at.to_le_bytes())?;
+        for slot in 0..SCHEMA.len() {
+            let value = offer.attributes.get(slot).copied().unwrap_or(MISSING);
+            w.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    let buckets: Vec<_> = tree
+        .buckets()
+        .map(|bucket| (false, bucket))
+        .chain(tree.window_buckets().map(|bucket| (true, bucket)))
+        .collect();
+    w.write_all(&(buckets.len() as u64).to_le_bytes())?;
+    for (flexible, (region_id, days, bucket)) in buckets {
+        w.write_all(&[region_id, flexible as u8])?;
+        w.write_all(&days.to_le_bytes())?;
+        w.write_all(&(bucket.len() as u64).to_le_bytes())?;
+        for offer in bucket.iter_sorted() {
+            w.write_all(&offer.start_date.to_le_bytes())?;
+            w.write_all(&offer.end_date.to_le_bytes())?;
+            w.write_all(&new_idx[offer.idx as usize].to_le_bytes())?;
+        }
+    }
+
+    w.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+    std::fs::rename(tmp_path, path)
+}
+
+/// Reads a snapshot written by [`write_snapshot`].
+pub fn read_snapshot(path: &Path) -> io::Result<(DenseStore, IndexTree)> {
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut r = BufReader::new(file);
+
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC || read_u32(&mut r)? != VERSION {
+        return Err(invalid_data(
+            "Not a clueless snapshot or unsupported version",
+        ));
+    }
+
+    let attribute_count = read_u32(&mut r)? as usize;
+    let names = (0..attribute_count)
+        .map(|_| read_str(&mut r))
+        .collect::<io::Result<Vec<_>>>()?;
+    if !names
+        .iter()
+        .eq(SCHEMA.attributes.iter().map(|attribute| &attribute.name))
+    {
+        return Err(invalid_data(
+            "Snapshot was written with a different attribute schema",
+        ));
+    }
+
+    let car_type_count = read_u32(&mut r)?;
+    let car_types = (0..car_type_count)
+        .map(|_| {
+            CAR_TYPES
+                .get(&read_str(&mut r)?)
+                .ok_or_else(|| invalid_data("Snapshot contains an unconfigured car type"))
+        })
+        .collect::<io::Result<Vec<CarType>>>()?;
+
+    // id, data, region, dates, seats, price, currency, flags, free km, expiry.
+    let offer_size = 4 + 4 + 4 + 8 + 8 + 4 + 4 + 3 + 3 + 4 + 8 + 4 * attribute_count as u64;
+    let offer_count = read_count(&mut r, offer_size, file_len)?;
+    let mut all = Vec::with_capacity(offer_count);
+    for idx in 0..offer_count as u32 {
+        let id = read_str(&mut r)?;
+        let data = read_str(&mut r)?;
+        let most_specific_region_id = read_u32(&mut r)?;
+        let start_date = read_u64(&mut r)?;
+        let end_date = read_u64(&mut r)?;
+        let number_seats = read_u32(&mut r)?;
+        let price = read_u32(&mut r)?;
+        let mut currency = [0; 3];
+        r.read_exact(&mut currency)?;
+        let mut flags = [0; 3];
+        r.read_exact(&mut flags)?;
+        let free_kilometers = read_u32(&mut r)?;
+        let expires_at = read_u64(&mut r)?;
+        let attributes = (0..attribute_count)
+            .map(|_| read_u32(&mut r))
+            .collect::<io::Result<_>>()?;
+        all.push(Offer {
+            idx,
+            id,
+            data,
+            most_specific_region_id,
+            start_date,
+            end_date,
+            number_seats,
+            price,
+            currency: Currency::from_bytes(currency)
+                .ok_or_else(|| invalid_data("Invalid currency in snapshot"))?,
+            car_type: *car_types
+                .get(flags[0] as usize)
+                .ok_or_else(|| invalid_data("Invalid car type in snapshot"))?,
+            has_vollkasko: flags[1] != 0,
+            free_kilometers,
+            expires_at,
+            flexible: flags[2] != 0,
+            attributes,
+        });
+    }
+    let mut store = DenseStore::from_offers(all);
+
+    let mut tree = IndexTree::populate_with_regions(&ROOT_REGION);
+    let mut free_intervals: FxHashMap<u32, Vec<(u64, u64)>> = FxHashMap::default();
+    let bucket_count = read_u64(&mut r)?;
+    for _ in 0..bucket_count {
+        let mut header = [0; 2];
+        r.read_exact(&mut header)?;
+        let (region_id, flexible) = (header[0], header[1] != 0);
+        let days = read_u32(&mut r)?;
+        let len = read_count(&mut r, 8 + 8 + 4, file_len)?;
+        if !tree.contains_region(region_id) {
+            return Err(invalid_data("Snapshot references an unknown region"));
+        }
+        let mut offers = Vec::with_capacity(len);
+        for _ in 0..len {
+            let (start_date, end_date, idx) =
+                (read_u64(&mut r)?, read_u64(&mut r)?, read_u32(&mut r)?);
+            let Some(stored) = store.all.get(idx as usize) else {
+                return Err(invalid_data("Snapshot index entry is out of bounds"));
+            };
+            let offer = IndexTreeOffer {
+                start_date,
+                end_date,
+                idx,
+                facet_key: stored.into(),
+            };
+            if flexible {
+                free_intervals
+                    .entry(offer.idx)
+                    .or_default()
+                    .push((offer.start_date, offer.end_date));
+            }
+            offers.push(offer);
+        }
+        tree.set_bucket(region_id, days, flexible, offers);
+    }
+
+    for (idx, mut intervals) in free_intervals {
+        let offer = &store.all[idx as usize];
+        if intervals != [(offer.start_date, offer.end_date)] {
+            intervals.sort_unstable();
+            store.set_free_intervals(idx, intervals);
+        }
+    }
+    Ok((store, tree))
+}
+
+fn write_str(w: &mut impl Write, value: &str) -> io::Result<()> {
+    w.write_all(&(value.len() as u32).to_le_bytes())?;
+    w.write_all(value.as_bytes())
+}
+
+fn read_str(r: &mut impl Read) -> io::Result<String> {
+    let len = read_u32(r)? as u64;
+    // Grows as bytes arrive rather than trusting the stored length.
+    let mut bytes = Vec::new();
+    r.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|_| invalid_data("Invalid UTF-8 in snapshot"))
+}
+
+/// Reads a record count, rejecting counts whose records could not fit into
+/// a file of `file_len` bytes so that a corrupt count never sizes an
+/// allocation.
+fn read_count(r: &mut impl Read, record_size: u64, file_len: u64) -> io::Result<usize> {
+    let count = read_u64(r)?;
+    if count > u32::MAX as u64 || count.saturating_mul(record_size) > file_len {
+        return Err(invalid_data("Implausible record count in snapshot"));
+    }
+    Ok(count as usize)
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_store_and_index() {
+        let offers: Vec<Offer> = (0..4u32)
+            .map(|idx| Offer {
+                idx,
+                id: format!("offer-{}", idx),
+                data: "ZGF0YQ==".to_string(),
+                most_specific_region_id: 7 + idx % 2,
+                start_date: 100 - idx as u64 * 10,
+                end_date: 200,
+                number_seats: 4 + idx,
+                price: 1000 * idx,
+                currency: if idx == 1 {
+                    "USD".parse().unwrap()
+                } else {
+                    Currency::EUR
+                },
+                car_type: CarType::from_id(idx as u8),
+                has_vollkasko: idx % 2 == 0,
+                free_kilometers: 50,
+                expires_at: u64::MAX,
+                flexible: idx == 3,
+                attributes: Box::default(),
+            })
+            .collect();
+        let mut tree = IndexTree::populate_with_regions(&ROOT_REGION);
+        tree.bulk_insert(&offers);
+        let booked = vec![(70, 100), (150, 200)];
+        tree.replace_free_intervals(&offers[3], &[(70, 200)], &booked);
+        let mut store = DenseStore::from_offers(offers);
+        store.set_free_intervals(3, booked.clone());
+
+        let path = std::env::temp_dir().join(format!("clueless-{}.snapshot", std::process::id()));
+        write_snapshot(&path, &store, &tree).unwrap();
+        let (loaded_store, loaded_tree) = read_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded_store.all.len(), 4);
+        assert_eq!(loaded_store.all[3].id, "offer-3");
+        assert_eq!(loaded_store.all[3].car_type.to_string(), "family");
+        assert!(!loaded_store.all[3].has_vollkasko);
+        assert!(loaded_store.all[3].flexible);
+        assert_eq!(loaded_store.all[1].currency.to_string(), "USD");
+        assert_eq!(loaded_store.free_intervals(3), booked);
+        assert_eq!(loaded_store.free_intervals(2), [(80, 200)]);
+        let entries = |tree: &IndexTree| {
+            let mut entries: Vec<_> = tree
+                .buckets()
+                .map(|(region_id, days, bucket)| {
+                    let offers: Vec<_> = bucket.iter_sorted().copied().collect();
+                    (region_id, days, offers)
+                })
+                .collect();
+            entries.sort_by_key(|(region_id, days, _)| (*region_id, *days));
+            entries
+        };
+        assert_eq!(entries(&loaded_tree), entries(&tree));
+        let window_entries: usize = loaded_tree
+            .window_buckets()
+            .map(|(_, _, bucket)| bucket.len())
+            .sum();
+        assert_eq!(window_entries, 2);
+    }
+
+    #[test]
+    fn rejects_lengths_past_the_end_of_the_file() {
+        let count = u64::MAX / 2;
+        let mut r = io::Cursor::new(count.to_le_bytes());
+        assert!(read_count(&mut r, 20, 1 << 20).is_err());
+        let mut r = io::Cursor::new(5u64.to_le_bytes());
+        assert_eq!(read_count(&mut r, 20, 1 << 20).unwrap(), 5);
+
+        let mut bytes = u32::MAX.to_le_bytes().to_vec();
+        bytes.extend_from_slice(b"abc");
+        assert!(read_str(&mut io::Cursor::new(bytes)).is_err());
+    }
+}
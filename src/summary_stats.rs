@@ -0,0 +1,144 @@
+use crate::json_models::SummaryStats;
+use std::collections::BTreeMap;
+
+/// Fields a search can request summary statistics for with `stats=`.
+pub const STAT_FIELDS: [&str; 2] = ["price", "freeKilometers"];
+
+/// Collects the values of one field over a search's candidates.
+#[derive(Debug, Default, Clone)]
+pub struct StatsCollector {
+    values: Vec<u32>,
+    sum: u64,
+}
+
+impl StatsCollector {
+    #[inline(always)]
+    pub fn add(&mut self, value: u32) {
+        self.values.push(value);
+        self.sum += value as u64;
+    }
+
+    pub fn merge(&mut self, other: StatsCollector) {
+        self.values.extend(other.values);
+        self.sum += other.sum;
+    }
+
+    /// Percentiles use the nearest-rank method, so every reported value is
+    /// one that occurs in the set.
+    pub fn finish(mut self) -> SummaryStats {
+        let count = self.values.len();
+        if count == 0 {
+            return SummaryStats::default();
+        }
+        let mut percentile = |p: usize| {
+            let rank = (p * count).div_ceil(100).max(1);
+            *self.values.select_nth_unstable(rank - 1).1
+        };
+        SummaryStats {
+            count: count as u32,
+            min: Some(percentile(0)),
+            p10: Some(percentile(10)),
+            median: Some(percentile(50)),
+            p90: Some(percentile(90)),
+            max: Some(percentile(100)),
+            average: Some(self.sum as f64 / count as f64),
+        }
+    }
+}
+
+/// The statistics requested by a search.
+#[derive(Debug, Default, Clone)]
+pub struct StatsRequest {
+    pub price: Option<StatsCollector>,
+    pub free_kilometers: Option<StatsCollector>,
+}
+
+impl StatsRequest {
+    /// Parses a comma-separated list of [`STAT_FIELDS`].
+    pub fn parse(spec: Option<&str>) -> Result<Self, &'static str> {
+        let mut request = StatsRequest::default();
+        let Some(spec) = spec else {
+            return Ok(request);
+        };
+        for field in spec.replace("%2C", ",").split(',') {
+            match field {
+                "price" => request.price = Some(StatsCollector::default()),
+                "freeKilometers" => request.free_kilometers = Some(StatsCollector::default()),
+                _ => return Err("Unknown stats field"),
+            }
+        }
+        Ok(request)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.price.is_none() && self.free_kilometers.is_none()
+    }
+
+    #[inline(always)]
+    pub fn add_price(&mut self, price: u32) {
+        if let Some(collector) = &mut self.price {
+            collector.add(price);
+        }
+    }
+
+    #[inline(always)]
+    pub fn add_free_kilometers(&mut self, free_kilometers: u32) {
+        if let Some(collector) = &mut self.free_kilometers {
+            collector.add(free_kilometers);
+        }
+    }
+
+    /// Adds the values another request with the same fields collected.
+    pub fn merge(&mut self, other: StatsRequest) {
+        for (collector, other) in [
+            (&mut self.price, other.price),
+            (&mut self.free_kilometers, other.free_kilometers),
+        ] {
+            if let (Some(collector), Some(other)) = (collector, other) {
+                collector.merge(other);
+            }
+        }
+    }
+
+    pub fn finish(self) -> BTreeMap<String, SummaryStats> {
+        [self.price, self.free_kilometers]
+            .into_iter()
+            .zip(STAT_FIELDS)
+            .filter_map(|(collector, name)| Some((name.to_string(), collector?.finish())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_nearest_rank_percentiles() {
+        let mut request = StatsRequest::parse(Some("price")).unwrap();
+        (1..=10)
+            .rev()
+            .for_each(|price| request.add_price(price * 100));
+        request.add_free_kilometers(7);
+        let stats = request.finish();
+        assert_eq!(stats.len(), 1);
+        let price = &stats["price"];
+        assert_eq!(price.count, 10);
+        assert_eq!((price.min, price.max), (Some(100), Some(1000)));
+        assert_eq!(price.p10, Some(100));
+        assert_eq!(price.median, Some(500));
+        assert_eq!(price.p90, Some(900));
+        assert_eq!(price.average, Some(550.0));
+    }
+
+    #[test]
+    fn reports_empty_sets_and_rejects_unknown_fields() {
+        let stats = StatsRequest::parse(Some("price,freeKilometers"))
+            .unwrap()
+            .finish();
+        assert_eq!(stats["freeKilometers"].count, 0);
+        assert_eq!(stats["freeKilometers"].min, None);
+        assert!(StatsRequest::parse(Some("price,seats")).is_err());
+        assert!(StatsRequest::parse(None).unwrap().finish().is_empty());
+    }
+}
@@ -0,0 +1,58 @@
+//! Blank offers and searches for tests to fill in the fields they care about.
+
+use crate::car_types::CarType;
+use crate::currency::Currency;
+use crate::db_models::Offer;
+use crate::json_models::{RequestOffer, SortOrder};
+
+/// An empty, never expiring EUR offer of the first configured car type.
+pub(crate) fn blank_offer() -> Offer {
+    Offer {
+        idx: 0,
+        id: String::new(),
+        data: String::new(),
+        most_specific_region_id: 0,
+        start_date: 0,
+        end_date: 0,
+        number_seats: 0,
+        price: 0,
+        currency: Currency::EUR,
+        car_type: CarType::from_id(0),
+        has_vollkasko: false,
+        free_kilometers: 0,
+        expires_at: u64::MAX,
+        flexible: false,
+        attributes: Box::default(),
+    }
+}
+
+/// A search of no regions without filters, facet widths or a page.
+pub(crate) fn blank_search() -> RequestOffer {
+    RequestOffer {
+        region_ids: Vec::new(),
+        excluded_region_ids: Vec::new(),
+        time_range_start: 0,
+        time_range_end: 0,
+        number_days: 0,
+        sort_order: SortOrder::PriceAsc,
+        page: 0,
+        page_size: 0,
+        price_range_width: 0,
+        min_free_kilometer_width: 0,
+        min_number_seats: None,
+        min_price: None,
+        max_price: None,
+        car_type: None,
+        only_vollkasko: None,
+        min_free_kilometer: None,
+        currency: None,
+        attribute_filters: Vec::new(),
+        price_buckets: None,
+        free_kilometer_buckets: None,
+        stats: None,
+        facets: None,
+        include_region: false,
+        near: None,
+        radius_km: None,
+    }
+}